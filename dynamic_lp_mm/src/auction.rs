@@ -0,0 +1,155 @@
+use anchor_lang::prelude::*;
+
+use crate::{math, VaultError};
+
+/// An open offer to sell `remaining` of one vault token for the other at a
+/// price moving linearly from `start_price` to `end_price` between
+/// `start_ts` and `end_ts`. Prices are vault prices (quote per base): the
+/// auction opens in the vault's favour and ends against it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RebalanceAuction {
+    pub sell_base:   bool,
+    pub remaining:   u64,
+    pub start_ts:    u64,
+    pub end_ts:      u64,
+    pub start_price: u64,
+    pub end_price:   u64,
+}
+
+impl RebalanceAuction {
+    /// Opens an auction around `oracle_price`: selling base starts
+    /// `premium_bps` above it and ends `discount_bps` below; buying base the
+    /// other way round.
+    pub fn open(
+        sell_base: bool,
+        amount: u64,
+        oracle_price: u64,
+        premium_bps: u16,
+        discount_bps: u16,
+        now: u64,
+        duration_sec: u64,
+    ) -> Option<Self> {
+        let above = math::mul_div(oracle_price as u128, 10_000 + premium_bps as u128, 10_000)?;
+        let below = math::mul_div(oracle_price as u128, 10_000 - discount_bps as u128, 10_000)?;
+        let (start_price, end_price) = if sell_base { (above, below) } else { (below, above) };
+        Some(Self {
+            sell_base,
+            remaining: amount,
+            start_ts: now,
+            end_ts: now.checked_add(duration_sec)?,
+            start_price: u64::try_from(start_price).ok()?,
+            end_price: u64::try_from(end_price).ok()?,
+        })
+    }
+
+    pub fn is_live(&self, now: u64) -> bool {
+        self.remaining > 0 && now <= self.end_ts
+    }
+
+    /// Auction price at `now`.
+    pub fn price_at(&self, now: u64) -> u64 {
+        let span = self.end_ts.saturating_sub(self.start_ts).max(1) as u128;
+        let elapsed = now.clamp(self.start_ts, self.end_ts).saturating_sub(self.start_ts) as u128;
+        let (a, b) = (self.start_price as u128, self.end_price as u128);
+        if a >= b {
+            (a - (a - b) * elapsed / span) as u64
+        } else {
+            (a + (b - a) * elapsed / span) as u64
+        }
+    }
+
+    /// What a solver pays for `amount` of the sold token at `price`,
+    /// rounded up in the vault's favour.
+    pub fn cost(&self, amount: u64, price: u64, base_decimals: u8, quote_decimals: u8) -> Option<u64> {
+        let base_scale = 10u128.checked_pow(base_decimals as u32)?;
+        let quote_scale = 10u128.checked_pow(quote_decimals as u32)?;
+        let cost = if self.sell_base {
+            math::mul_div_ceil(amount as u128 * price as u128, quote_scale, base_scale * math::PRICE_SCALE as u128)?
+        } else {
+            math::mul_div_ceil(amount as u128 * base_scale, math::PRICE_SCALE as u128, price as u128 * quote_scale)?
+        };
+        u64::try_from(cost).ok()
+    }
+}
+
+/// What the vault should sell to bring the base share of value back to
+/// `target_base_bps`, with the sold value capped at `max_notional` quote.
+/// Returns `(sell_base, amount)`.
+pub fn rebalance_amount(
+    base_amount: u64,
+    base_value: u64,
+    quote_amount: u64,
+    target_base_bps: u16,
+    max_notional: u64,
+) -> Option<(bool, u64)> {
+    let total = base_value as u128 + quote_amount as u128;
+    let target = total * target_base_bps as u128 / 10_000;
+    if base_value as u128 > target {
+        let excess = (base_value as u128 - target).min(max_notional as u128);
+        let amount = math::mul_div(base_amount as u128, excess, base_value as u128)?;
+        Some((true, amount as u64))
+    } else {
+        let excess = (target - base_value as u128).min(max_notional as u128);
+        Some((false, excess as u64))
+    }
+}
+
+pub fn validate_config(duration_sec: u64, premium_bps: u16, discount_bps: u16) -> Result<()> {
+    require!(
+        premium_bps <= 10_000 && discount_bps < 10_000 && (duration_sec > 0 || (premium_bps == 0 && discount_bps == 0)),
+        VaultError::InvalidAuctionConfig
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORACLE: u64 = 100 * math::PRICE_SCALE;
+
+    #[test]
+    fn selling_base_decays_from_premium_to_discount() {
+        let a = RebalanceAuction::open(true, 1_000, ORACLE, 200, 100, 1_000, 100).unwrap();
+        assert_eq!(a.price_at(900), 102 * math::PRICE_SCALE);
+        assert_eq!(a.price_at(1_000), 102 * math::PRICE_SCALE);
+        assert_eq!(a.price_at(1_050), 100_500_000);
+        assert_eq!(a.price_at(1_100), 99 * math::PRICE_SCALE);
+        assert_eq!(a.price_at(2_000), 99 * math::PRICE_SCALE);
+        assert!(a.is_live(1_100) && !a.is_live(1_101));
+    }
+
+    #[test]
+    fn buying_base_rises_from_discount_to_premium() {
+        let a = RebalanceAuction::open(false, 1_000, ORACLE, 200, 100, 0, 10).unwrap();
+        assert_eq!(a.price_at(0), 99 * math::PRICE_SCALE);
+        assert_eq!(a.price_at(10), 102 * math::PRICE_SCALE);
+    }
+
+    #[test]
+    fn cost_rounds_in_vault_favour() {
+        // base has 9 decimals, quote 6
+        let sell = RebalanceAuction::open(true, 0, ORACLE, 0, 0, 0, 1).unwrap();
+        assert_eq!(sell.cost(1_000_000_000, ORACLE, 9, 6), Some(100_000_000));
+        assert_eq!(sell.cost(1, ORACLE, 9, 6), Some(1));
+        let buy = RebalanceAuction::open(false, 0, ORACLE, 0, 0, 0, 1).unwrap();
+        assert_eq!(buy.cost(100_000_000, ORACLE, 9, 6), Some(1_000_000_000));
+        assert_eq!(buy.cost(1, ORACLE, 9, 6), Some(10));
+    }
+
+    #[test]
+    fn sizes_toward_target_within_cap() {
+        // 1_000 base worth 3_000 quote vs 1_000 quote: sell 1_000 quote of base
+        assert_eq!(rebalance_amount(1_000, 3_000, 1_000, 5_000, u64::MAX), Some((true, 333)));
+        assert_eq!(rebalance_amount(1_000, 3_000, 1_000, 5_000, 300), Some((true, 100)));
+        assert_eq!(rebalance_amount(0, 0, 1_000, 5_000, u64::MAX), Some((false, 500)));
+    }
+
+    #[test]
+    fn validates_config() {
+        assert!(validate_config(600, 50, 50).is_ok());
+        assert!(validate_config(0, 0, 0).is_ok());
+        assert!(validate_config(0, 50, 0).is_err());
+        assert!(validate_config(600, 0, 10_000).is_err());
+    }
+}
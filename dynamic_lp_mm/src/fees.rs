@@ -0,0 +1,215 @@
+use anchor_lang::prelude::*;
+
+use crate::nav::{self, VIRTUAL_SHARES, VIRTUAL_VALUE};
+use crate::{math, PoolFeesCollected, Vault, VaultError};
+
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Highest annual management fee.
+pub const MAX_MANAGEMENT_FEE_BPS: u16 = 1_000;
+
+pub const MAX_FEE_RECIPIENTS: usize = 5;
+
+/// Share of swept fees paid to `recipient`'s token accounts.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeeRecipient {
+    pub recipient: Pubkey,
+    pub bps:       u16,
+}
+
+/// `fee_bps` of `amount`, rounded down.
+pub fn protocol_cut(amount: u64, fee_bps: u16) -> u64 {
+    (amount as u128 * fee_bps.min(10_000) as u128 / 10_000) as u64
+}
+
+/// Splits fees collected from the venue: `Vault::fee_bps` of each side is
+/// accrued for the treasury and the rest stays in the vault to compound.
+/// Returns the accrued cut.
+pub fn accrue_pool_fees(vault: &mut Vault, base: u64, quote: u64) -> Result<(u64, u64)> {
+    let cut_base = protocol_cut(base, vault.fee_bps);
+    let cut_quote = protocol_cut(quote, vault.fee_bps);
+    vault.accrued_fee_base = vault.accrued_fee_base.checked_add(cut_base).ok_or(VaultError::MathOverflow)?;
+    vault.accrued_fee_quote = vault.accrued_fee_quote.checked_add(cut_quote).ok_or(VaultError::MathOverflow)?;
+    if base > 0 || quote > 0 {
+        emit!(PoolFeesCollected {
+            base,
+            quote,
+            protocol_base: cut_base,
+            protocol_quote: cut_quote,
+        });
+    }
+    Ok((cut_base, cut_quote))
+}
+
+/// Shares to mint to the treasury for `perf_fee_bps` of the value gained
+/// above `high_water_mark`, and the mark to keep. NAV per share is as in
+/// `nav::nav_per_share`. A vault without a mark only sets it, and NAV per
+/// share at or below the mark charges nothing and keeps it, so a drawdown
+/// is recovered fee-free. After a charge the mark is the diluted NAV per
+/// share.
+pub fn performance_fee(nav_value: u64, supply: u64, high_water_mark: u64, perf_fee_bps: u16) -> Option<(u64, u64)> {
+    let per_share = nav::nav_per_share(nav_value, supply);
+    if high_water_mark == 0 {
+        return Some((0, per_share));
+    }
+    if supply == 0 || per_share <= high_water_mark {
+        return Some((0, high_water_mark));
+    }
+    let total_shares = supply as u128 + VIRTUAL_SHARES as u128;
+    let total_value = nav_value as u128 + VIRTUAL_VALUE as u128;
+    let gain = math::mul_div((per_share - high_water_mark) as u128, total_shares, math::PRICE_SCALE as u128)?;
+    let fee_value = gain * perf_fee_bps.min(10_000) as u128 / 10_000;
+    // dilute holders by exactly `fee_value`: shares / (total + shares) of the NAV
+    let fee_shares = u64::try_from(math::mul_div(fee_value, total_shares, total_value.checked_sub(fee_value)?)?).ok()?;
+    Some((fee_shares, nav::nav_per_share(nav_value, supply.checked_add(fee_shares)?)))
+}
+
+/// Shares to mint to the treasury for `management_fee_bps` a year of the
+/// vault, streamed over `elapsed` seconds: `shares * f / (1 - f)` with
+/// `f = bps * elapsed / (10_000 * SECONDS_PER_YEAR)`, so holders are diluted
+/// by exactly `f`. At most a year is charged at a time.
+pub fn management_fee_shares(supply: u64, management_fee_bps: u16, elapsed: u64) -> Option<u64> {
+    if supply == 0 {
+        return Some(0);
+    }
+    let total_shares = supply as u128 + VIRTUAL_SHARES as u128;
+    let rate = management_fee_bps.min(MAX_MANAGEMENT_FEE_BPS) as u128 * elapsed.min(SECONDS_PER_YEAR) as u128;
+    let scale = 10_000 * SECONDS_PER_YEAR as u128;
+    u64::try_from(math::mul_div(total_shares, rate, scale - rate)?).ok()
+}
+
+/// Accepts an empty table (everything goes to the treasury) or up to
+/// `MAX_FEE_RECIPIENTS` distinct recipients with non-zero weights summing
+/// to 10,000 bps.
+pub fn validate_fee_recipients(recipients: &[FeeRecipient]) -> Result<()> {
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    require!(recipients.len() <= MAX_FEE_RECIPIENTS, VaultError::InvalidFeeRecipients);
+    let mut total = 0u32;
+    for (i, r) in recipients.iter().enumerate() {
+        require!(
+            r.bps > 0 && !recipients[..i].iter().any(|o| o.recipient == r.recipient),
+            VaultError::InvalidFeeRecipients
+        );
+        total += r.bps as u32;
+    }
+    require!(total == 10_000, VaultError::InvalidFeeRecipients);
+    Ok(())
+}
+
+/// Splits `amount` by the recipients' weights. Rounding dust goes to the
+/// last recipient, so the payouts add up to `amount`.
+pub fn split_fees(amount: u64, recipients: &[FeeRecipient]) -> Vec<u64> {
+    let mut left = amount;
+    recipients
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let share = if i + 1 == recipients.len() {
+                left
+            } else {
+                (amount as u128 * r.bps as u128 / 10_000) as u64
+            };
+            left -= share;
+            share
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUPPLY: u64 = 1_999_999_000;
+
+    #[test]
+    fn cut_rounds_down_and_caps_at_everything() {
+        assert_eq!(protocol_cut(1_000, 250), 25);
+        assert_eq!(protocol_cut(39, 250), 0);
+        assert_eq!(protocol_cut(1_000, 0), 0);
+        assert_eq!(protocol_cut(1_000, u16::MAX), 1_000);
+        assert_eq!(protocol_cut(u64::MAX, 10_000), u64::MAX);
+    }
+
+    #[test]
+    fn first_charge_only_sets_the_mark() {
+        assert_eq!(performance_fee(1_999_999_000, SUPPLY, 0, 2_000), Some((0, 1_000_000)));
+        // no shares out: nothing to charge, the mark stays
+        assert_eq!(performance_fee(5_000_000, 0, 1_000_000, 2_000), Some((0, 1_000_000)));
+    }
+
+    #[test]
+    fn charges_only_the_gain_above_the_mark() {
+        // NAV per share 1.0 -> 1.1: 20% of the 200_000_000 gain
+        let (shares, mark) = performance_fee(2_199_999_000, SUPPLY, 1_000_000, 2_000).unwrap();
+        assert_eq!((shares, mark), (37_037_037, 1_080_000));
+        let fee_value = math::mul_div(2_200_000_000, shares as u128, (SUPPLY + VIRTUAL_SHARES + shares) as u128);
+        assert_eq!(fee_value, Some(39_999_999));
+        // a 0 bps fee still moves the mark up
+        assert_eq!(performance_fee(2_199_999_000, SUPPLY, 1_000_000, 0), Some((0, 1_100_000)));
+    }
+
+    #[test]
+    fn drawdown_then_recovery_is_not_charged_twice() {
+        let (shares, mark) = performance_fee(2_199_999_000, SUPPLY, 1_000_000, 2_000).unwrap();
+        let supply = SUPPLY + shares;
+        // drawdown: no fee, the mark holds
+        assert_eq!(performance_fee(1_799_999_000, supply, mark, 2_000), Some((0, mark)));
+        // recovery back to the mark: still nothing
+        assert_eq!(performance_fee(2_199_998_999, supply, mark, 2_000), Some((0, mark)));
+        // only the new high is charged: 1.08 -> 1.178, not 0.88 -> 1.178
+        let (shares, new_mark) = performance_fee(2_399_999_000, supply, mark, 2_000).unwrap();
+        assert_eq!((shares, new_mark), (34_525_758, 1_158_545));
+    }
+
+    #[test]
+    fn management_fee_streams_over_time() {
+        // 2% a year dilutes holders by 2%
+        let shares = management_fee_shares(SUPPLY, 200, SECONDS_PER_YEAR).unwrap();
+        assert_eq!(shares, 40_816_326);
+        let total = (SUPPLY + VIRTUAL_SHARES) as u128;
+        assert_eq!(total * 10_000 / (total + shares as u128), 9_800);
+        // two half years come to about the same
+        let first = management_fee_shares(SUPPLY, 200, SECONDS_PER_YEAR / 2).unwrap();
+        let second = management_fee_shares(SUPPLY + first, 200, SECONDS_PER_YEAR / 2).unwrap();
+        assert_eq!(first, 20_202_020);
+        assert!((first + second).abs_diff(shares) < shares / 100);
+        // nothing to dilute, nothing elapsed, no fee
+        assert_eq!(management_fee_shares(0, 200, SECONDS_PER_YEAR), Some(0));
+        assert_eq!(management_fee_shares(SUPPLY, 200, 0), Some(0));
+        assert_eq!(management_fee_shares(SUPPLY, 0, SECONDS_PER_YEAR), Some(0));
+        // a long idle stretch is charged for a year at most
+        assert_eq!(management_fee_shares(SUPPLY, 200, 10 * SECONDS_PER_YEAR), Some(shares));
+    }
+
+    fn recipient(byte: u8, bps: u16) -> FeeRecipient {
+        FeeRecipient { recipient: Pubkey::new_from_array([byte; 32]), bps }
+    }
+
+    #[test]
+    fn fee_recipient_tables_are_validated() {
+        assert!(validate_fee_recipients(&[]).is_ok());
+        assert!(validate_fee_recipients(&[recipient(1, 10_000)]).is_ok());
+        assert!(validate_fee_recipients(&[recipient(1, 7_000), recipient(2, 3_000)]).is_ok());
+        let invalid = [
+            vec![recipient(1, 9_999)],
+            vec![recipient(1, 7_000), recipient(2, 3_001)],
+            vec![recipient(1, 10_000), recipient(2, 0)],
+            vec![recipient(1, 5_000), recipient(1, 5_000)],
+            (1..=6).map(|i| recipient(i, if i == 1 { 5_000 } else { 1_000 })).collect(),
+        ];
+        for table in invalid {
+            assert_eq!(validate_fee_recipients(&table), err!(VaultError::InvalidFeeRecipients));
+        }
+    }
+
+    #[test]
+    fn fees_split_pro_rata_with_dust_to_the_last() {
+        let table = [recipient(1, 6_000), recipient(2, 3_000), recipient(3, 1_000)];
+        assert_eq!(split_fees(1_000_000, &table), vec![600_000, 300_000, 100_000]);
+        assert_eq!(split_fees(999, &table), vec![599, 299, 101]);
+        assert_eq!(split_fees(0, &table), vec![0, 0, 0]);
+        assert_eq!(split_fees(u64::MAX, &table).iter().map(|&x| x as u128).sum::<u128>(), u64::MAX as u128);
+    }
+}
@@ -0,0 +1,175 @@
+use anchor_lang::prelude::*;
+
+use crate::VaultError;
+
+/// Most bands on either side of the active one.
+pub const MAX_LADDER_HALF_WIDTH: u8 = 4;
+
+/// exp(-2 * i^2 / k^2) * 1000 for half width `k` and offset `i`, i.e. a
+/// gaussian with the outermost bands at two standard deviations.
+const GAUSSIAN_WEIGHTS: [[u64; 5]; 5] = [
+    [1000, 0, 0, 0, 0],
+    [1000, 135, 0, 0, 0],
+    [1000, 607, 135, 0, 0],
+    [1000, 801, 411, 135, 0],
+    [1000, 882, 607, 325, 135],
+];
+
+/// How liquidity is spread across the ladder.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LadderShape {
+    /// Equal weight in every band.
+    #[default]
+    Uniform,
+    /// Weight falls off linearly from the active band.
+    Triangular,
+    /// Weight falls off as a gaussian around the active band.
+    Gaussian,
+    /// Bands below the active one share `bid_bps` of the side weight, bands
+    /// above it the rest.
+    Skewed { bid_bps: u16 },
+}
+
+/// Target share of deployed liquidity for one band.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BandAllocation {
+    pub band:       i64,
+    pub weight_bps: u16,
+}
+
+/// Bands the vault spreads liquidity across, `half_width` on either side of
+/// `center`, and the allocation table and band width they were last
+/// deployed with.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Ladder {
+    pub shape:       LadderShape,
+    pub half_width:  u8,
+    pub center:      i64,
+    pub band_bp:     u16,
+    pub allocations: Vec<BandAllocation>,
+}
+
+impl Ladder {
+    pub fn validate(shape: LadderShape, half_width: u8) -> Result<()> {
+        let shape_ok = match shape {
+            LadderShape::Skewed { bid_bps } => bid_bps <= 10_000,
+            _ => true,
+        };
+        require!(shape_ok && half_width <= MAX_LADDER_HALF_WIDTH, VaultError::InvalidLadder);
+        Ok(())
+    }
+
+    /// Whether moving to `center` on a `band_bp` grid redeploys every band:
+    /// the centre is unchanged, or bands kept in place would still span
+    /// ranges of the old width.
+    pub fn needs_rebuild(&self, center: i64, band_bp: u16) -> bool {
+        center == self.center || band_bp != self.band_bp
+    }
+
+    /// Allocation table for a ladder centred on `center`. Weights sum to
+    /// 10_000, with rounding dust going to the centre band.
+    pub fn allocation_table(&self, center: i64) -> Vec<BandAllocation> {
+        let k = self.half_width.min(MAX_LADDER_HALF_WIDTH) as i64;
+        let raw: Vec<u64> = (-k..=k)
+            .map(|i| match self.shape {
+                LadderShape::Uniform => 1,
+                LadderShape::Triangular => (k + 1 - i.abs()) as u64,
+                LadderShape::Gaussian => GAUSSIAN_WEIGHTS[k as usize][i.unsigned_abs() as usize],
+                LadderShape::Skewed { bid_bps } => match i.signum() {
+                    -1 => bid_bps as u64,
+                    1 => 10_000 - bid_bps as u64,
+                    _ => 5_000,
+                },
+            })
+            .collect();
+        let total: u64 = raw.iter().sum();
+        let mut table: Vec<BandAllocation> = (-k..=k)
+            .zip(&raw)
+            .map(|(i, w)| BandAllocation {
+                band:       center + i,
+                weight_bps: (w * 10_000 / total) as u16,
+            })
+            .collect();
+        let dust = 10_000 - table.iter().map(|a| a.weight_bps as u64).sum::<u64>();
+        table[k as usize].weight_bps += dust as u16;
+        table
+    }
+}
+
+/// Weight of `band` in `table`, zero if it is not part of it.
+pub fn weight_of(table: &[BandAllocation], band: i64) -> u16 {
+    table.iter().find(|a| a.band == band).map_or(0, |a| a.weight_bps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ladder(shape: LadderShape, half_width: u8) -> Ladder {
+        Ladder { shape, half_width, center: 0, band_bp: 5, allocations: Vec::new() }
+    }
+
+    fn weights(table: &[BandAllocation]) -> Vec<u16> {
+        table.iter().map(|a| a.weight_bps).collect()
+    }
+
+    #[test]
+    fn single_band_gets_everything() {
+        for shape in [LadderShape::Uniform, LadderShape::Gaussian, LadderShape::Skewed { bid_bps: 0 }] {
+            assert_eq!(ladder(shape, 0).allocation_table(-7), vec![BandAllocation { band: -7, weight_bps: 10_000 }]);
+        }
+    }
+
+    #[test]
+    fn shapes_sum_to_full_allocation() {
+        let shapes = [
+            LadderShape::Uniform,
+            LadderShape::Triangular,
+            LadderShape::Gaussian,
+            LadderShape::Skewed { bid_bps: 7_000 },
+            LadderShape::Skewed { bid_bps: 10_000 },
+        ];
+        for shape in shapes {
+            for k in 0..=MAX_LADDER_HALF_WIDTH {
+                let table = ladder(shape, k).allocation_table(100);
+                assert_eq!(table.len(), 2 * k as usize + 1);
+                assert_eq!(table.first().unwrap().band, 100 - k as i64);
+                assert_eq!(table.iter().map(|a| a.weight_bps as u32).sum::<u32>(), 10_000);
+            }
+        }
+    }
+
+    #[test]
+    fn shape_weights() {
+        assert_eq!(weights(&ladder(LadderShape::Uniform, 1).allocation_table(0)), [3_333, 3_334, 3_333]);
+        assert_eq!(weights(&ladder(LadderShape::Triangular, 1).allocation_table(0)), [2_500, 5_000, 2_500]);
+        let gaussian = weights(&ladder(LadderShape::Gaussian, 2).allocation_table(0));
+        assert!(gaussian[2] > gaussian[1] && gaussian[1] > gaussian[0] && gaussian[0] == gaussian[4]);
+        let skewed = weights(&ladder(LadderShape::Skewed { bid_bps: 7_500 }, 1).allocation_table(0));
+        assert_eq!(skewed, [5_000, 3_334, 1_666]);
+    }
+
+    #[test]
+    fn weight_lookup() {
+        let table = ladder(LadderShape::Triangular, 1).allocation_table(10);
+        assert_eq!(weight_of(&table, 11), 2_500);
+        assert_eq!(weight_of(&table, 12), 0);
+    }
+
+    #[test]
+    fn width_change_rebuilds_in_place() {
+        let l = ladder(LadderShape::Uniform, 2);
+        assert!(l.needs_rebuild(0, 5));
+        assert!(!l.needs_rebuild(1, 5));
+        // band 0 overlaps both tables, but a 10 bp band 0 is not a 5 bp one
+        assert!(l.needs_rebuild(1, 10));
+        assert!(l.needs_rebuild(0, 10));
+    }
+
+    #[test]
+    fn validates_parameters() {
+        assert!(Ladder::validate(LadderShape::Gaussian, MAX_LADDER_HALF_WIDTH).is_ok());
+        assert!(Ladder::validate(LadderShape::Uniform, MAX_LADDER_HALF_WIDTH + 1).is_err());
+        assert!(Ladder::validate(LadderShape::Skewed { bid_bps: 10_001 }, 1).is_err());
+    }
+}
@@ -1,6 +1,30 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token::{self, Mint, MintTo, Burn, TokenAccount, Token, Transfer};
 
+pub mod auction;
+pub mod fees;
+pub mod ladder;
+pub mod math;
+pub mod nav;
+pub mod oracle;
+pub mod referral;
+pub mod strategy;
+pub mod swap;
+pub mod twap;
+pub mod venue;
+pub mod zap;
+
+use auction::RebalanceAuction;
+use ladder::{Ladder, LadderShape};
+use fees::FeeRecipient;
+use nav::{Nav, NavSnapshot};
+use oracle::OracleFeed;
+use strategy::{RebalanceMode, TriggerState};
+use swap::{SwapLeg, SwapResult};
+use twap::{PriceObservation, PRICE_HISTORY_LEN};
+use venue::{ClobState, LpPosition, VenueAccounts, VenueKind};
+use zap::TokenSide;
+
 declare_id!("57y2Lg2TEBxTvnfo5Jokj21SsAVeZyc6ijANBYXhm9bc");
 
 #[program]
@@ -12,30 +36,78 @@ pub mod dynamic_lp_mm {
         fee_bps: u16,
         band_size_bp: u16,
     ) -> Result<()> {
+        require!(band_size_bp > 0, VaultError::InvalidBandSize);
+        require!(fee_bps <= 10_000, VaultError::InvalidFee);
         let v = &mut ctx.accounts.vault;
         v.authority = *ctx.accounts.authority.key;
         v.admins = Vec::new();
         v.treasury = ctx.accounts.treasury.key();
         v.base_mint = ctx.accounts.base_mint.key();
         v.quote_mint = ctx.accounts.quote_mint.key();
+        v.base_decimals = ctx.accounts.base_mint.decimals;
+        v.quote_decimals = ctx.accounts.quote_mint.decimals;
         v.share_mint = ctx.accounts.share_mint.key();
         v.base_vault = ctx.accounts.base_vault.key();
         v.quote_vault = ctx.accounts.quote_vault.key();
         v.fee_bps = fee_bps;
         v.withdraw_fee_bps = 0;
+        v.zap_fee_bps = 0;
+        v.perf_fee_bps = 0;
+        v.management_fee_bps = 0;
         v.band_size_bp = band_size_bp;
+        v.band_min_bp = 0;
+        v.band_max_bp = 0;
+        v.band_vol_multiplier_bps = 0;
+        v.effective_band_bp = band_size_bp;
         v.min_deposit_amount = 0;
         v.max_total_deposit = 0;
         v.last_band = 0;
         v.rebalance_cooldown_sec = 300;
         v.last_rebalance_ts = 0;
-        v.rebalance_mode = 0;
+        v.last_rebalance_price = 0;
+        v.rebalance_mode = RebalanceMode::Manual;
         v.paused = false;
         v.emergency_withdraw_only = false;
         v.accrued_fee_base = 0;
         v.accrued_fee_quote = 0;
+        v.high_water_mark = 0;
+        v.last_fee_accrual_ts = Clock::get()?.unix_timestamp as u64;
+        v.min_harvest_value = 0;
+        v.fee_recipients = Vec::new();
+        v.referral_share_bps = 0;
+        v.referral_share_vault = Pubkey::default();
+        v.referred_shares = 0;
+        v.referral_fee_index = 0;
+        v.referral_owed_base = 0;
+        v.referral_owed_quote = 0;
         v.deposit_whitelist = Vec::new();
         v.withdraw_whitelist = Vec::new();
+        v.venue = VenueKind::Idle;
+        v.venue_program = Pubkey::default();
+        v.venue_market = Pubkey::default();
+        v.positions = Vec::new();
+        v.ladder = Ladder::default();
+        v.target_base_bps = 5_000;
+        v.max_inventory_skew_bps = 0;
+        v.swap_program = Pubkey::default();
+        v.max_swap_slippage_bps = 0;
+        v.max_swap_notional = 0;
+        v.auction_duration_sec = 0;
+        v.auction_premium_bps = 0;
+        v.auction_discount_bps = 0;
+        v.auction = None;
+        v.clob = ClobState::default();
+        v.oracle_feed_id = [0; 32];
+        v.oracle_max_age_sec = 60;
+        v.oracle_max_conf_bps = 100;
+        v.oracle_config = Pubkey::default();
+        v.price_history = Pubkey::default();
+        v.twap_window_sec = 0;
+        v.keeper_bounty_lamports = 0;
+        v.keeper_budget_lamports = 0;
+        v.keeper_epoch_cap_lamports = 0;
+        v.keeper_epoch = 0;
+        v.keeper_paid_this_epoch = 0;
         v.bump = ctx.bumps.vault;
         Ok(())
     }
@@ -47,9 +119,11 @@ pub mod dynamic_lp_mm {
         Ok(())
     }
 
+    /// Sets the protocol's cut of pool fees collected from the venue.
     pub fn update_fee(ctx: Context<UpdateFee>, fee_bps: u16) -> Result<()> {
         let v = &mut ctx.accounts.vault;
         require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(fee_bps <= 10_000, VaultError::InvalidFee);
         v.fee_bps = fee_bps;
         Ok(())
     }
@@ -61,6 +135,81 @@ pub mod dynamic_lp_mm {
         Ok(())
     }
 
+    /// Fee on the part of a single-sided deposit or withdrawal that is
+    /// swapped against the vault's inventory.
+    pub fn set_zap_fee(ctx: Context<UpdateFee>, zap_fee_bps: u16) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(zap_fee_bps <= 10_000, VaultError::InvalidZapFee);
+        v.zap_fee_bps = zap_fee_bps;
+        Ok(())
+    }
+
+    /// Fee on NAV per share gained above the high-water mark, charged on
+    /// rebalance by minting shares to the treasury.
+    pub fn set_performance_fee(ctx: Context<UpdateFee>, perf_fee_bps: u16) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(perf_fee_bps <= 10_000, VaultError::InvalidFee);
+        v.perf_fee_bps = perf_fee_bps;
+        Ok(())
+    }
+
+    /// Annual management fee, streamed to the treasury as share dilution.
+    /// Fees owed at the old rate are accrued first.
+    pub fn set_management_fee(ctx: Context<SetManagementFee>, management_fee_bps: u16) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(management_fee_bps <= fees::MAX_MANAGEMENT_FEE_BPS, VaultError::InvalidFee);
+        accrue_management_fee(
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            &ctx.accounts.token_program,
+            Clock::get()?.unix_timestamp,
+        )?;
+        ctx.accounts.vault.management_fee_bps = management_fee_bps;
+        Ok(())
+    }
+
+    /// Smallest harvest, in quote atoms of value, `harvest` will carry out.
+    pub fn set_min_harvest_value(ctx: Context<UpdateFee>, min_harvest_value: u64) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        v.min_harvest_value = min_harvest_value;
+        Ok(())
+    }
+
+    /// Splits swept fees between up to `MAX_FEE_RECIPIENTS` recipients by
+    /// weight. An empty table sends everything to the treasury.
+    pub fn set_fee_recipients(ctx: Context<UpdateFee>, recipients: Vec<FeeRecipient>) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        fees::validate_fee_recipients(&recipients)?;
+        v.fee_recipients = recipients;
+        Ok(())
+    }
+
+    /// Creates the token account escrowing referrers' performance fee shares.
+    pub fn init_referral_share_vault(ctx: Context<InitReferralShareVault>) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        ctx.accounts.vault.referral_share_vault = ctx.accounts.referral_share_vault.key();
+        Ok(())
+    }
+
+    /// Share of referred users' withdrawal and performance fees paid to
+    /// their referrer.
+    pub fn set_referral_share(ctx: Context<UpdateFee>, referral_share_bps: u16) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(referral_share_bps <= 10_000, VaultError::InvalidFee);
+        require!(
+            referral_share_bps == 0 || v.referral_share_vault != Pubkey::default(),
+            VaultError::ReferralsNotInitialized
+        );
+        v.referral_share_bps = referral_share_bps;
+        Ok(())
+    }
+
     pub fn set_rebalance_cooldown(ctx: Context<UpdateFee>, cooldown_sec: u64) -> Result<()> {
         let v = &mut ctx.accounts.vault;
         require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
@@ -68,13 +217,249 @@ pub mod dynamic_lp_mm {
         Ok(())
     }
 
-    pub fn set_rebalance_mode(ctx: Context<UpdateFee>, mode: u8) -> Result<()> {
+    /// `InventorySkew` also becomes the inventory target's `max_skew_bps`,
+    /// so the skew that triggers a rebalance is the one that moves the ladder.
+    pub fn set_rebalance_mode(ctx: Context<UpdateFee>, mode: RebalanceMode) -> Result<()> {
         let v = &mut ctx.accounts.vault;
         require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        mode.validate()?;
+        if let RebalanceMode::InventorySkew { max_skew_bps } = mode {
+            v.max_inventory_skew_bps = max_skew_bps;
+        }
         v.rebalance_mode = mode;
         Ok(())
     }
 
+    /// Enables volatility-adaptive band sizing: the band becomes
+    /// `volatility * vol_multiplier_bps / 10_000`, clamped to `[min_bp, max_bp]`.
+    /// All zeros turns it off.
+    pub fn set_band_range(ctx: Context<UpdateFee>, min_bp: u16, max_bp: u16, vol_multiplier_bps: u16) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        let off = min_bp == 0 && max_bp == 0 && vol_multiplier_bps == 0;
+        require!(
+            off || (min_bp > 0 && min_bp <= max_bp && vol_multiplier_bps > 0),
+            VaultError::InvalidBandRange
+        );
+        v.band_min_bp = min_bp;
+        v.band_max_bp = max_bp;
+        v.band_vol_multiplier_bps = vol_multiplier_bps;
+        Ok(())
+    }
+
+    /// Spreads liquidity over `half_width` bands either side of the active
+    /// one. Takes effect on the next rebalance.
+    pub fn set_ladder(ctx: Context<UpdateFee>, shape: LadderShape, half_width: u8) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        Ladder::validate(shape, half_width)?;
+        v.ladder.shape = shape;
+        v.ladder.half_width = half_width;
+        Ok(())
+    }
+
+    /// Sets the base share of value the vault aims to hold. Past
+    /// `max_skew_bps` off target, rebalances move the ladder to mean-revert
+    /// inventory; `0` turns that off. In `InventorySkew` mode `max_skew_bps`
+    /// is the trigger's threshold too and must be valid for it.
+    pub fn set_inventory_target(ctx: Context<UpdateFee>, target_base_bps: u16, max_skew_bps: u16) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(
+            target_base_bps <= 10_000 && max_skew_bps <= 10_000,
+            VaultError::InvalidInventoryTarget
+        );
+        if let RebalanceMode::InventorySkew { .. } = v.rebalance_mode {
+            let mode = RebalanceMode::InventorySkew { max_skew_bps };
+            mode.validate()?;
+            v.rebalance_mode = mode;
+        }
+        v.target_base_bps = target_base_bps;
+        v.max_inventory_skew_bps = max_skew_bps;
+        Ok(())
+    }
+
+    /// Whitelists the program rebalance swap legs may route through and
+    /// bounds each swap. `Pubkey::default()` disables swaps.
+    pub fn set_swap_config(
+        ctx: Context<UpdateFee>,
+        swap_program: Pubkey,
+        max_slippage_bps: u16,
+        max_notional: u64,
+    ) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(max_slippage_bps <= 10_000, VaultError::InvalidSwapConfig);
+        v.swap_program = swap_program;
+        v.max_swap_slippage_bps = max_slippage_bps;
+        v.max_swap_notional = max_notional;
+        Ok(())
+    }
+
+    /// Lets rebalances post a Dutch auction for the inventory past
+    /// `max_inventory_skew_bps`, capped at `max_swap_notional`. `duration_sec`
+    /// of `0` turns auctions off.
+    pub fn set_auction_config(
+        ctx: Context<UpdateFee>,
+        duration_sec: u64,
+        premium_bps: u16,
+        discount_bps: u16,
+    ) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        auction::validate_config(duration_sec, premium_bps, discount_bps)?;
+        v.auction_duration_sec = duration_sec;
+        v.auction_premium_bps = premium_bps;
+        v.auction_discount_bps = discount_bps;
+        Ok(())
+    }
+
+    pub fn set_oracle(
+        ctx: Context<UpdateFee>,
+        feed_id: [u8; 32],
+        max_age_sec: u64,
+        max_conf_bps: u16,
+    ) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(max_conf_bps <= 10_000, VaultError::InvalidOracleConfig);
+        v.oracle_feed_id = feed_id;
+        v.oracle_max_age_sec = max_age_sec;
+        v.oracle_max_conf_bps = max_conf_bps;
+        Ok(())
+    }
+
+    pub fn init_oracle_config(
+        ctx: Context<InitOracleConfig>,
+        feeds: Vec<OracleFeed>,
+        max_divergence_bps: u16,
+    ) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        validate_oracle_feeds(&feeds, max_divergence_bps)?;
+        let c = &mut ctx.accounts.oracle_config;
+        c.vault = ctx.accounts.vault.key();
+        c.feeds = feeds;
+        c.max_divergence_bps = max_divergence_bps;
+        c.bump = ctx.bumps.oracle_config;
+        ctx.accounts.vault.oracle_config = c.key();
+        Ok(())
+    }
+
+    pub fn set_oracle_feeds(
+        ctx: Context<UpdateOracleConfig>,
+        feeds: Vec<OracleFeed>,
+        max_divergence_bps: u16,
+    ) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        validate_oracle_feeds(&feeds, max_divergence_bps)?;
+        let c = &mut ctx.accounts.oracle_config;
+        c.feeds = feeds;
+        c.max_divergence_bps = max_divergence_bps;
+        Ok(())
+    }
+
+    pub fn init_price_history(ctx: Context<InitPriceHistory>, min_interval_sec: u64) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        let h = &mut ctx.accounts.price_history;
+        h.vault = ctx.accounts.vault.key();
+        h.min_interval_sec = min_interval_sec;
+        h.bump = ctx.bumps.price_history;
+        ctx.accounts.vault.price_history = h.key();
+        Ok(())
+    }
+
+    pub fn set_twap_window(ctx: Context<UpdateFee>, window_sec: u64) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(
+            window_sec == 0 || v.price_history != Pubkey::default(),
+            VaultError::InsufficientPriceHistory
+        );
+        v.twap_window_sec = window_sec;
+        Ok(())
+    }
+
+    pub fn record_price(ctx: Context<RecordPrice>) -> Result<()> {
+        let vault_ref = &ctx.accounts.vault;
+        require!(
+            ctx.accounts.oracle_config.is_some() == (vault_ref.oracle_config != Pubkey::default()),
+            VaultError::InvalidOracleConfig
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let config = ctx.accounts.oracle_config.as_deref();
+        let (prices, _) = oracle::read_prices(vault_ref, config, ctx.remaining_accounts, now, false)?;
+        let price = oracle::median(&prices);
+        ctx.accounts.price_history.record(now, price)
+    }
+
+    pub fn set_venue(
+        ctx: Context<UpdateFee>,
+        venue: VenueKind,
+        venue_program: Pubkey,
+        venue_market: Pubkey,
+    ) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(!venue::has_open_position(v), VaultError::PositionOpen);
+        v.venue = venue;
+        v.venue_program = venue_program;
+        v.venue_market = venue_market;
+        Ok(())
+    }
+
+    pub fn set_quote_config(
+        ctx: Context<UpdateFee>,
+        spread_bps: u16,
+        level_step_bps: u16,
+        levels: u8,
+        order_size: u64,
+        base_lot_size: u64,
+        quote_lot_size: u64,
+    ) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(
+            levels <= venue::MAX_QUOTE_LEVELS
+                && base_lot_size > 0
+                && quote_lot_size > 0
+                && (spread_bps as u64) + (levels as u64) * (level_step_bps as u64) < 10_000,
+            VaultError::InvalidQuoteConfig
+        );
+        v.clob.spread_bps = spread_bps;
+        v.clob.level_step_bps = level_step_bps;
+        v.clob.levels = levels;
+        v.clob.order_size = order_size;
+        v.clob.base_lot_size = base_lot_size;
+        v.clob.quote_lot_size = quote_lot_size;
+        Ok(())
+    }
+
+    pub fn init_open_orders<'info>(
+        ctx: Context<'_, '_, '_, 'info, InitOpenOrders<'info>>,
+        name: String,
+    ) -> Result<()> {
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let payer_info = ctx.accounts.authority.to_account_info();
+        let system_program_info = ctx.accounts.system_program.to_account_info();
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        require!(v.venue == VenueKind::OpenBookV2, VaultError::InvalidVenueAccounts);
+        require!(v.clob.open_orders == Pubkey::default(), VaultError::PositionOpen);
+        let base_mint = v.base_mint;
+        let quote_mint = v.quote_mint;
+        let bump = [v.bump];
+        let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+        venue::init_open_orders(
+            v,
+            &vault_info,
+            &payer_info,
+            &system_program_info,
+            ctx.remaining_accounts,
+            &[seeds],
+            name,
+        )
+    }
+
     pub fn add_admin(ctx: Context<ModifyAdmins>, admin: Pubkey) -> Result<()> {
         let v = &mut ctx.accounts.vault;
         require!(ctx.accounts.authority.key == &v.authority, VaultError::Unauthorized);
@@ -123,119 +508,110 @@ pub mod dynamic_lp_mm {
         Ok(())
     }
 
-    pub fn deposit(ctx: Context<Deposit>, base_amount: u64, quote_amount: u64) -> Result<()> {
+    pub fn deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        base_amount: u64,
+        quote_amount: u64,
+        min_shares_out: u64,
+        deadline: Option<i64>,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        check_deadline(deadline, now)?;
+        ctx.accounts.accrue_management_fee(now)?;
         let vault_ref = &ctx.accounts.vault;
-        require!(!vault_ref.paused, VaultError::VaultPaused);
-        require!(!vault_ref.emergency_withdraw_only, VaultError::EmergencyMode);
+        check_deposit_allowed(vault_ref, ctx.accounts.user.key)?;
 
-        if !vault_ref.deposit_whitelist.is_empty() {
-            require!(
-                vault_ref.deposit_whitelist.contains(&ctx.accounts.user.key()),
-                VaultError::NotWhitelisted
-            );
-        }
-        if vault_ref.min_deposit_amount > 0 {
-            require!(
-                base_amount >= vault_ref.min_deposit_amount
-                    && quote_amount >= vault_ref.min_deposit_amount,
-                VaultError::BelowMinDeposit
-            );
-        }
-        if vault_ref.max_total_deposit > 0 {
-            require!(
-                ctx.accounts
-                    .base_vault
-                    .amount
-                    .checked_add(base_amount)
-                    .unwrap()
-                    <= vault_ref.max_total_deposit
-                    && ctx
-                        .accounts
-                        .quote_vault
-                        .amount
-                        .checked_add(quote_amount)
-                        .unwrap()
-                        <= vault_ref.max_total_deposit,
-                VaultError::AboveMaxTotal
-            );
-        }
+        // price the deposit against everything the vault holds, deployed or not
+        let (price, used) =
+            oracle_price(vault_ref, ctx.accounts.oracle_config.as_deref(), ctx.remaining_accounts, now)?;
+        let nav = vault_nav(
+            vault_ref,
+            &ctx.accounts.base_vault,
+            &ctx.accounts.quote_vault,
+            &ctx.remaining_accounts[used..],
+            price,
+        )?;
+        nav::check_min_deposit(&nav, base_amount, quote_amount, vault_ref.min_deposit_amount)?;
+        // only take the amounts that match the vault's current mix
+        let (base_used, quote_used) = nav.ratio_matched(base_amount, quote_amount).ok_or(VaultError::MathOverflow)?;
+        check_max_total(vault_ref, &ctx.accounts.base_vault, &ctx.accounts.quote_vault, base_used, quote_used)?;
 
+        let (bd, qd) = (vault_ref.base_decimals, vault_ref.quote_decimals);
+        let nav_value = nav.value(price, bd, qd).ok_or(VaultError::MathOverflow)?;
+        let deposit_value = Nav { base: base_used, quote: quote_used }
+            .value(price, bd, qd)
+            .ok_or(VaultError::MathOverflow)?;
         let supply = ctx.accounts.share_mint.supply;
-        let vault_base = ctx.accounts.base_vault.amount;
-        let vault_quote = ctx.accounts.quote_vault.amount;
-        let shares = if supply == 0 {
-            base_amount.checked_add(quote_amount).unwrap()
-        } else {
-            let sb = base_amount
-                .checked_mul(supply)
-                .unwrap()
-                .checked_div(vault_base)
-                .unwrap();
-            let sq = quote_amount
-                .checked_mul(supply)
-                .unwrap()
-                .checked_div(vault_quote)
-                .unwrap();
-            sb.min(sq)
-        };
-
-        // transfer in
-        token::transfer(ctx.accounts.transfer_base_to_vault_ctx(), base_amount)?;
-        token::transfer(ctx.accounts.transfer_quote_to_vault_ctx(), quote_amount)?;
-
-        // auto‐compound: clear old fees
-        {
-            let mut vault = &mut ctx.accounts.vault;
-            vault.accrued_fee_base = 0;
-            vault.accrued_fee_quote = 0;
-        }
+        let shares = nav::deposit_shares(deposit_value, nav_value, supply)?;
+        require!(shares >= min_shares_out, VaultError::SharesOutBelowMinimum);
 
-        // prepare seeds
-        let bump = ctx.accounts.vault.bump;
-        let base_mint = ctx.accounts.vault.base_mint;
-        let quote_mint = ctx.accounts.vault.quote_mint;
-
-        // mint shares
-        token::mint_to(
-            ctx.accounts
-                .mint_shares_ctx()
-                .with_signer(&[&[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]]]),
+        ctx.accounts.settle(
+            (base_used, quote_used),
+            (base_amount - base_used, quote_amount - quote_used),
             shares,
+            now,
         )?;
-
-        Ok(())
+        ctx.accounts.record_referral(referrer, shares, ctx.bumps.referral)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, share_amount: u64) -> Result<()> {
+    /// Deposits a single token. The part that would have to be swapped to
+    /// match the vault's mix is taken into inventory at the oracle price
+    /// less `zap_fee_bps`. `remaining_accounts` as for `deposit`.
+    pub fn deposit_single<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        side: TokenSide,
+        amount: u64,
+        min_shares: u64,
+        deadline: Option<i64>,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        check_deadline(deadline, now)?;
+        ctx.accounts.accrue_management_fee(now)?;
         let vault_ref = &ctx.accounts.vault;
-        require!(!vault_ref.paused, VaultError::VaultPaused);
-        if vault_ref.emergency_withdraw_only == false && !vault_ref.withdraw_whitelist.is_empty() {
-            require!(
-                vault_ref.withdraw_whitelist.contains(&ctx.accounts.user.key()),
-                VaultError::NotWhitelisted
-            );
-        }
+        check_deposit_allowed(vault_ref, ctx.accounts.user.key)?;
+        require!(amount >= vault_ref.min_deposit_amount, VaultError::BelowMinDeposit);
+        let (base_used, quote_used) = match side {
+            TokenSide::Base => (amount, 0),
+            TokenSide::Quote => (0, amount),
+        };
+        check_max_total(vault_ref, &ctx.accounts.base_vault, &ctx.accounts.quote_vault, base_used, quote_used)?;
 
+        let (price, used) =
+            oracle_price(vault_ref, ctx.accounts.oracle_config.as_deref(), ctx.remaining_accounts, now)?;
+        let nav = vault_nav(
+            vault_ref,
+            &ctx.accounts.base_vault,
+            &ctx.accounts.quote_vault,
+            &ctx.remaining_accounts[used..],
+            price,
+        )?;
+        let (bd, qd) = (vault_ref.base_decimals, vault_ref.quote_decimals);
+        let nav_value = nav.value(price, bd, qd).ok_or(VaultError::MathOverflow)?;
+        let deposit_value = zap::zap_in_value(&nav, side, amount, price, bd, qd, vault_ref.zap_fee_bps)
+            .ok_or(VaultError::MathOverflow)?;
         let supply = ctx.accounts.share_mint.supply;
-        let vb = ctx.accounts.base_vault.amount;
-        let vq = ctx.accounts.quote_vault.amount;
-        let ba = share_amount.checked_mul(vb).unwrap().checked_div(supply).unwrap();
-        let qa = share_amount.checked_mul(vq).unwrap().checked_div(supply).unwrap();
-
-        let fee_b = ba
-            .checked_mul(vault_ref.withdraw_fee_bps as u64)
-            .unwrap()
-            .checked_div(10_000)
-            .unwrap();
-        let fee_q = qa
-            .checked_mul(vault_ref.withdraw_fee_bps as u64)
-            .unwrap()
-            .checked_div(10_000)
-            .unwrap();
-        let nb = ba.checked_sub(fee_b).unwrap();
-        let nq = qa.checked_sub(fee_q).unwrap();
-
-        token::burn(ctx.accounts.burn_shares_ctx(), share_amount)?;
+        let shares = nav::deposit_shares(deposit_value, nav_value, supply)?;
+        require!(shares >= min_shares, VaultError::SharesOutBelowMinimum);
+
+        ctx.accounts.settle((base_used, quote_used), (0, 0), shares, now)?;
+        ctx.accounts.record_referral(referrer, shares, ctx.bumps.referral)
+    }
+
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+        share_amount: u64,
+        min_base_out: u64,
+        min_quote_out: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline, Clock::get()?.unix_timestamp)?;
+        let (nb, nq) = redeem_shares(ctx.accounts, ctx.remaining_accounts, share_amount, None)?;
+        require!(nb >= min_base_out, VaultError::BaseOutBelowMinimum);
+        require!(nq >= min_quote_out, VaultError::QuoteOutBelowMinimum);
+
+        let vault_ref = &ctx.accounts.vault;
         token::transfer(
             ctx.accounts
                 .transfer_base_to_user_ctx()
@@ -259,90 +635,1020 @@ pub mod dynamic_lp_mm {
             nq,
         )?;
 
-        // update fees and auto‐compound
-        {
-            let mut vault = &mut ctx.accounts.vault;
-            vault.accrued_fee_base = vault.accrued_fee_base.checked_add(fee_b).unwrap();
-            vault.accrued_fee_quote = vault.accrued_fee_quote.checked_add(fee_q).unwrap();
-            // immediately reinvest
-            vault.accrued_fee_base = 0;
-            vault.accrued_fee_quote = 0;
+        Ok(())
+    }
+
+    /// Withdraws in a single token. The holder's claim on the other token is
+    /// sold to the vault at the oracle price less `zap_fee_bps` and paid out
+    /// of idle inventory. `remaining_accounts`: the oracle accounts, then as
+    /// for `withdraw`.
+    pub fn withdraw_single<'info>(
+        ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+        side: TokenSide,
+        shares: u64,
+        min_out: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        check_deadline(deadline, now)?;
+        let (price, used) = oracle_price(
+            &ctx.accounts.vault,
+            ctx.accounts.oracle_config.as_deref(),
+            ctx.remaining_accounts,
+            now,
+        )?;
+        let claim = redeem_shares(ctx.accounts, &ctx.remaining_accounts[used..], shares, Some(price))?;
+
+        let vault_ref = &ctx.accounts.vault;
+        let out = zap::zap_out_amount(
+            claim,
+            side,
+            price,
+            vault_ref.base_decimals,
+            vault_ref.quote_decimals,
+            vault_ref.zap_fee_bps,
+        )
+        .ok_or(VaultError::MathOverflow)?;
+        let seeds: &[&[u8]] = &[
+            b"vault",
+            vault_ref.base_mint.as_ref(),
+            vault_ref.quote_mint.as_ref(),
+            &[vault_ref.bump],
+        ];
+        match side {
+            TokenSide::Base => {
+                require!(out >= min_out, VaultError::BaseOutBelowMinimum);
+                require!(
+                    out <= ctx.accounts.base_vault.amount.saturating_sub(vault_ref.accrued_fee_base),
+                    VaultError::InsufficientZapLiquidity
+                );
+                token::transfer(ctx.accounts.transfer_base_to_user_ctx().with_signer(&[seeds]), out)?;
+            }
+            TokenSide::Quote => {
+                require!(out >= min_out, VaultError::QuoteOutBelowMinimum);
+                require!(
+                    out <= ctx.accounts.quote_vault.amount.saturating_sub(vault_ref.accrued_fee_quote),
+                    VaultError::InsufficientZapLiquidity
+                );
+                token::transfer(ctx.accounts.transfer_quote_to_user_ctx().with_signer(&[seeds]), out)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Read-only: reports the vault's NAV at the oracle price through return
+    /// data. `remaining_accounts`: the oracle accounts, then the venue
+    /// accounts read by `venue::deployed_amounts`.
+    pub fn get_nav<'info>(ctx: Context<'_, '_, '_, 'info, GetNav<'info>>) -> Result<NavSnapshot> {
+        let v = &ctx.accounts.vault;
+        let now = Clock::get()?.unix_timestamp;
+        let (price, used) = oracle_price(v, ctx.accounts.oracle_config.as_deref(), ctx.remaining_accounts, now)?;
+        let nav = vault_nav(v, &ctx.accounts.base_vault, &ctx.accounts.quote_vault, &ctx.remaining_accounts[used..], price)?;
+        let total_value = nav.value(price, v.base_decimals, v.quote_decimals).ok_or(VaultError::MathOverflow)?;
+        let share_supply = ctx.accounts.share_mint.supply;
+        Ok(NavSnapshot {
+            price,
+            base: nav.base,
+            quote: nav.quote,
+            total_value,
+            share_supply,
+            nav_per_share: nav::nav_per_share(total_value, share_supply),
+        })
+    }
 
+    pub fn rebalance<'info>(
+        ctx: Context<'_, '_, '_, 'info, Rebalance<'info>>,
+        current_price: u64,
+        swap_leg: Option<SwapLeg>,
+    ) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        let now = Clock::get()?.unix_timestamp as u64;
+        rebalance_to_price(
+            &mut ctx.accounts.vault,
+            &ctx.accounts.base_vault,
+            &ctx.accounts.quote_vault,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            ctx.accounts.referral_share_vault.as_ref(),
+            &ctx.accounts.token_program,
+            ctx.remaining_accounts,
+            ctx.accounts.price_history.as_deref(),
+            swap_leg.as_ref(),
+            current_price,
+            now,
+        )?;
+        Ok(())
+    }
+
+    pub fn rebalance_oracle<'info>(
+        ctx: Context<'_, '_, '_, 'info, RebalanceOracle<'info>>,
+        swap_leg: Option<SwapLeg>,
+    ) -> Result<()> {
+        require!(is_admin(&ctx.accounts.vault, ctx.accounts.authority.key), VaultError::Unauthorized);
+        let now = Clock::get()?.unix_timestamp;
+        let Some((price, used)) = oracle_rebalance_price(
+            &ctx.accounts.vault,
+            ctx.accounts.oracle_config.as_deref(),
+            ctx.accounts.price_history.as_deref(),
+            ctx.remaining_accounts,
+            now,
+        )?
+        else {
+            return Ok(());
+        };
+        rebalance_to_price(
+            &mut ctx.accounts.vault,
+            &ctx.accounts.base_vault,
+            &ctx.accounts.quote_vault,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            ctx.accounts.referral_share_vault.as_ref(),
+            &ctx.accounts.token_program,
+            &ctx.remaining_accounts[used..],
+            ctx.accounts.price_history.as_deref(),
+            swap_leg.as_ref(),
+            price,
+            now as u64,
+        )?;
         Ok(())
     }
 
-    pub fn rebalance(ctx: Context<Rebalance>, current_price: u64) -> Result<()> {
+    /// Permissionless `rebalance_oracle` for keepers. Only runs in automated
+    /// modes, and pays the caller the keeper bounty when the band moves.
+    pub fn crank_rebalance<'info>(ctx: Context<'_, '_, '_, 'info, CrankRebalance<'info>>) -> Result<()> {
+        require!(
+            ctx.accounts.vault.rebalance_mode != RebalanceMode::Manual,
+            VaultError::ManualRebalanceOnly
+        );
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let Some((price, used)) = oracle_rebalance_price(
+            &ctx.accounts.vault,
+            ctx.accounts.oracle_config.as_deref(),
+            ctx.accounts.price_history.as_deref(),
+            ctx.remaining_accounts,
+            now,
+        )?
+        else {
+            return Ok(());
+        };
+        let moved = rebalance_to_price(
+            &mut ctx.accounts.vault,
+            &ctx.accounts.base_vault,
+            &ctx.accounts.quote_vault,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            ctx.accounts.referral_share_vault.as_ref(),
+            &ctx.accounts.token_program,
+            &ctx.remaining_accounts[used..],
+            ctx.accounts.price_history.as_deref(),
+            None,
+            price,
+            now as u64,
+        )?;
+        if !moved {
+            return Ok(());
+        }
+
         let v = &mut ctx.accounts.vault;
+        if v.keeper_epoch != clock.epoch {
+            v.keeper_epoch = clock.epoch;
+            v.keeper_paid_this_epoch = 0;
+        }
+        let bounty = v
+            .keeper_bounty_lamports
+            .min(v.keeper_budget_lamports)
+            .min(v.keeper_epoch_cap_lamports.saturating_sub(v.keeper_paid_this_epoch));
+        if bounty > 0 {
+            v.keeper_budget_lamports -= bounty;
+            v.keeper_paid_this_epoch += bounty;
+            // the budget sits on top of the vault's rent-exempt balance
+            **v.to_account_info().try_borrow_mut_lamports()? -= bounty;
+            **ctx.accounts.keeper.to_account_info().try_borrow_mut_lamports()? += bounty;
+            emit!(KeeperPaid {
+                keeper: ctx.accounts.keeper.key(),
+                amount: bounty,
+                epoch: clock.epoch,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn set_keeper_bounty(
+        ctx: Context<UpdateFee>,
+        bounty_lamports: u64,
+        epoch_cap_lamports: u64,
+    ) -> Result<()> {
+        let v = &mut ctx.accounts.vault;
+        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+        v.keeper_bounty_lamports = bounty_lamports;
+        v.keeper_epoch_cap_lamports = epoch_cap_lamports;
+        Ok(())
+    }
+
+    /// Anyone may top up the keeper budget.
+    pub fn fund_keeper_budget(ctx: Context<FundKeeperBudget>, lamports: u64) -> Result<()> {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.funder.to_account_info(),
+                    to:   ctx.accounts.vault.to_account_info(),
+                },
+            ),
+            lamports,
+        )?;
+        let v = &mut ctx.accounts.vault;
+        v.keeper_budget_lamports = v.keeper_budget_lamports.checked_add(lamports).ok_or(VaultError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>) -> Result<()> {
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let base_vault_info = ctx.accounts.base_vault.to_account_info();
+        let quote_vault_info = ctx.accounts.quote_vault.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let v = &mut ctx.accounts.vault;
+        let base_mint = v.base_mint;
+        let quote_mint = v.quote_mint;
+        let bump = [v.bump];
+        let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+        venue::settle_funds(
+            v,
+            &VenueAccounts {
+                vault:         vault_info,
+                base_vault:    base_vault_info,
+                quote_vault:   quote_vault_info,
+                token_program: token_program_info,
+                remaining:     ctx.remaining_accounts,
+                signer_seeds:  &[seeds],
+            },
+        )
+    }
+
+    /// Permissionless. Collects fees and rewards from the deployed positions,
+    /// accrues the protocol's cut and re-deposits the rest into the active
+    /// band, then charges the performance fee. Fails with
+    /// `HarvestBelowMinimum` unless the harvest is worth at least
+    /// `min_harvest_value` at the oracle price. `remaining_accounts`: the
+    /// oracle accounts, the venue's NAV accounts, then the venue accounts.
+    pub fn harvest<'info>(ctx: Context<'_, '_, '_, 'info, Harvest<'info>>) -> Result<()> {
+        require!(!ctx.accounts.vault.paused, VaultError::VaultPaused);
+        require!(!ctx.accounts.vault.emergency_withdraw_only, VaultError::EmergencyMode);
+        let now = Clock::get()?.unix_timestamp;
+        accrue_management_fee(
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            &ctx.accounts.token_program,
+            now,
+        )?;
+        let (price, used) = oracle_price(
+            &ctx.accounts.vault,
+            ctx.accounts.oracle_config.as_deref(),
+            ctx.remaining_accounts,
+            now,
+        )?;
+        let nav_accounts = &ctx.remaining_accounts[used..];
+        let (_, nav_used) = venue::deployed_amounts(&ctx.accounts.vault, nav_accounts, Some(price))?;
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let base_vault_info = ctx.accounts.base_vault.to_account_info();
+        let quote_vault_info = ctx.accounts.quote_vault.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let v = &mut ctx.accounts.vault;
+        let base_mint = v.base_mint;
+        let quote_mint = v.quote_mint;
+        let bump = [v.bump];
+        let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+        let band = v.ladder.center;
+        let harvest = venue::harvest(
+            v,
+            &VenueAccounts {
+                vault:         vault_info,
+                base_vault:    base_vault_info,
+                quote_vault:   quote_vault_info,
+                token_program: token_program_info,
+                remaining:     &nav_accounts[nav_used..],
+                signer_seeds:  &[seeds],
+            },
+            band,
+        )?;
+        let value = Nav { base: harvest.base, quote: harvest.quote }
+            .value(price, v.base_decimals, v.quote_decimals)
+            .ok_or(VaultError::MathOverflow)?;
+        require!(value > 0 && value >= v.min_harvest_value, VaultError::HarvestBelowMinimum);
+        emit!(Harvested {
+            base: harvest.base,
+            quote: harvest.quote,
+            protocol_base: harvest.protocol_base,
+            protocol_quote: harvest.protocol_quote,
+            compounded_base: harvest.compounded_base,
+            compounded_quote: harvest.compounded_quote,
+            value,
+            timestamp: now as u64,
+        });
+
+        // the harvest is in NAV now, so gains above the mark are charged
+        ctx.accounts.base_vault.reload()?;
+        ctx.accounts.quote_vault.reload()?;
+        let v = &mut ctx.accounts.vault;
+        let nav_value = vault_nav(v, &ctx.accounts.base_vault, &ctx.accounts.quote_vault, nav_accounts, price)?
+            .value(price, v.base_decimals, v.quote_decimals)
+            .ok_or(VaultError::MathOverflow)?;
+        charge_performance_fee(
+            v,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            ctx.accounts.referral_share_vault.as_ref(),
+            &ctx.accounts.token_program,
+            nav_value,
+            now as u64,
+        )
+    }
+
+    /// Buys `amount` of the token the live rebalance auction sells, paying
+    /// at the current auction price but no more than `max_cost`.
+    pub fn fill_rebalance_auction(ctx: Context<FillRebalanceAuction>, amount: u64, max_cost: u64) -> Result<()> {
+        let v = &ctx.accounts.vault;
         require!(!v.paused, VaultError::VaultPaused);
         require!(!v.emergency_withdraw_only, VaultError::EmergencyMode);
         let now = Clock::get()?.unix_timestamp as u64;
+        let mut a = v.auction.filter(|a| a.is_live(now)).ok_or(VaultError::NoLiveAuction)?;
+        require!(amount > 0 && amount <= a.remaining, VaultError::InvalidAuctionFill);
+        let price = a.price_at(now);
+        let cost = a.cost(amount, price, v.base_decimals, v.quote_decimals).ok_or(VaultError::MathOverflow)?;
+        require!(cost <= max_cost, VaultError::AuctionCostExceeded);
+
+        let base_mint = v.base_mint;
+        let quote_mint = v.quote_mint;
+        let bump = [v.bump];
+        let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+        let accs = &ctx.accounts;
+        let (pay_from, pay_to, send_from, send_to) = if a.sell_base {
+            (&accs.solver_quote_ata, &accs.quote_vault, &accs.base_vault, &accs.solver_base_ata)
+        } else {
+            (&accs.solver_base_ata, &accs.base_vault, &accs.quote_vault, &accs.solver_quote_ata)
+        };
+        token::transfer(
+            CpiContext::new(accs.token_program.to_account_info(), Transfer {
+                from: pay_from.to_account_info(),
+                to: pay_to.to_account_info(),
+                authority: accs.solver.to_account_info(),
+            }),
+            cost,
+        )?;
+        token::transfer(
+            CpiContext::new(accs.token_program.to_account_info(), Transfer {
+                from: send_from.to_account_info(),
+                to: send_to.to_account_info(),
+                authority: accs.vault.to_account_info(),
+            })
+            .with_signer(&[seeds]),
+            amount,
+        )?;
+
+        a.remaining -= amount;
+        emit!(AuctionFilled {
+            solver: accs.solver.key(),
+            sell_base: a.sell_base,
+            amount,
+            cost,
+            price,
+            remaining: a.remaining,
+        });
+        ctx.accounts.vault.auction = Some(a);
+        Ok(())
+    }
+
+    /// Permissionless. Mints the management fee streamed since the last
+    /// accrual to the treasury.
+    pub fn accrue_fees(ctx: Context<AccrueFees>) -> Result<()> {
+        accrue_management_fee(
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.share_mint,
+            &ctx.accounts.treasury_share_ata,
+            &ctx.accounts.token_program,
+            Clock::get()?.unix_timestamp,
+        )
+    }
+
+    /// Pays a referrer what they have earned from one referred user:
+    /// withdrawal fees in base and quote, performance fees in shares.
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let fee_index = ctx.accounts.vault.referral_fee_index;
+        let referral = &mut ctx.accounts.referral;
+        referral.settle(fee_index).ok_or(VaultError::MathOverflow)?;
+        let (base, quote, shares) = (referral.owed_base, referral.owed_quote, referral.owed_shares);
+        referral.owed_base = 0;
+        referral.owed_quote = 0;
+        referral.owed_shares = 0;
+
+        let accounts = &ctx.accounts;
+        accounts.pay(&accounts.base_vault, &accounts.referrer_base_ata, base)?;
+        accounts.pay(&accounts.quote_vault, &accounts.referrer_quote_ata, quote)?;
+        if shares > 0 {
+            let escrow = accounts.referral_share_vault.as_ref().ok_or(VaultError::ReferralsNotInitialized)?;
+            accounts.pay(escrow, &accounts.referrer_share_ata, shares)?;
+        }
+
+        let v = &mut ctx.accounts.vault;
+        v.accrued_fee_base = v.accrued_fee_base.checked_sub(base).ok_or(VaultError::MathOverflow)?;
+        v.accrued_fee_quote = v.accrued_fee_quote.checked_sub(quote).ok_or(VaultError::MathOverflow)?;
+        v.referral_owed_base = v.referral_owed_base.checked_sub(base).ok_or(VaultError::MathOverflow)?;
+        v.referral_owed_quote = v.referral_owed_quote.checked_sub(quote).ok_or(VaultError::MathOverflow)?;
+        emit!(ReferralRewardsClaimed {
+            referrer: ctx.accounts.referrer.key(),
+            user: ctx.accounts.referral.user,
+            base,
+            quote,
+            shares,
+        });
+        Ok(())
+    }
+
+    /// Pays the accrued fees to the fee recipients pro rata, or to the
+    /// treasury while the table is empty. `remaining_accounts`: the base and
+    /// quote token accounts of every recipient, in table order.
+    pub fn sweep_fees<'info>(ctx: Context<'_, '_, '_, 'info, SweepFees<'info>>) -> Result<()> {
+        let vault_ref = &ctx.accounts.vault;
+        require!(is_admin(vault_ref, ctx.accounts.authority.key), VaultError::Unauthorized);
+
+        // snapshot fees & seeds; referrers' part stays until claimed
+        let fb = vault_ref
+            .accrued_fee_base
+            .checked_sub(vault_ref.referral_owed_base)
+            .ok_or(VaultError::MathOverflow)?;
+        let fq = vault_ref
+            .accrued_fee_quote
+            .checked_sub(vault_ref.referral_owed_quote)
+            .ok_or(VaultError::MathOverflow)?;
+        let bump = vault_ref.bump;
+        let base_mint = vault_ref.base_mint;
+        let quote_mint = vault_ref.quote_mint;
+
+        if !vault_ref.fee_recipients.is_empty() {
+            let recipients = vault_ref.fee_recipients.clone();
+            require!(
+                ctx.remaining_accounts.len() == 2 * recipients.len(),
+                VaultError::InvalidFeeRecipients
+            );
+            let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]];
+            let base_split = fees::split_fees(fb, &recipients);
+            let quote_split = fees::split_fees(fq, &recipients);
+            for (i, (r, accounts)) in recipients.iter().zip(ctx.remaining_accounts.chunks(2)).enumerate() {
+                for (account, mint, from, amount) in [
+                    (&accounts[0], base_mint, &ctx.accounts.base_vault, base_split[i]),
+                    (&accounts[1], quote_mint, &ctx.accounts.quote_vault, quote_split[i]),
+                ] {
+                    let ata = TokenAccount::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+                    require!(
+                        ata.owner == r.recipient && ata.mint == mint,
+                        VaultError::InvalidFeeRecipients
+                    );
+                    if amount > 0 {
+                        token::transfer(
+                            CpiContext::new_with_signer(
+                                ctx.accounts.token_program.to_account_info(),
+                                Transfer {
+                                    from:      from.to_account_info(),
+                                    to:        account.clone(),
+                                    authority: ctx.accounts.vault.to_account_info(),
+                                },
+                                &[seeds],
+                            ),
+                            amount,
+                        )?;
+                    }
+                }
+                emit!(FeePaid { recipient: r.recipient, base: base_split[i], quote: quote_split[i] });
+            }
+        } else {
+            ctx.accounts.pay_treasury(fb, fq)?;
+            emit!(FeePaid { recipient: ctx.accounts.vault.treasury, base: fb, quote: fq });
+        }
+
+        // clear accrued fees
+        let vault = &mut ctx.accounts.vault;
+        vault.accrued_fee_base = vault.referral_owed_base;
+        vault.accrued_fee_quote = vault.referral_owed_quote;
+
+        Ok(())
+    }
+}
+
+// -- Helpers, State, Events, Errors, Contexts (unchanged) --
+
+fn is_admin(v: &Vault, key: &Pubkey) -> bool {
+    *key == v.authority || v.admins.contains(key)
+}
+
+fn validate_oracle_feeds(feeds: &[OracleFeed], max_divergence_bps: u16) -> Result<()> {
+    require!(
+        !feeds.is_empty() && feeds.len() <= oracle::MAX_ORACLE_FEEDS && max_divergence_bps <= 10_000,
+        VaultError::InvalidOracleConfig
+    );
+    Ok(())
+}
+
+/// Price `rebalance_oracle` moves bands on, and how many `remaining_accounts`
+/// the oracle reads used. `None` when the divergence breaker tripped.
+fn oracle_rebalance_price(
+    v: &Vault,
+    config: Option<&OracleConfig>,
+    history: Option<&PriceHistory>,
+    remaining: &[AccountInfo],
+    now: i64,
+) -> Result<Option<(u64, usize)>> {
+    require!(
+        config.is_some() == (v.oracle_config != Pubkey::default()),
+        VaultError::InvalidOracleConfig
+    );
+    let (prices, used) = oracle::read_prices(v, config, remaining, now, true)?;
+    let median = oracle::median(&prices);
+    if let Some(c) = config {
+        if let Some((i, divergence_bps)) = oracle::find_divergence(&prices, median, c.max_divergence_bps) {
+            // circuit breaker: keep the current band until the sources agree
+            emit!(OracleDivergence {
+                feed_index: i as u8,
+                price: prices[i],
+                median,
+                divergence_bps,
+                timestamp: now as u64,
+            });
+            return Ok(None);
+        }
+    }
+    // bands follow the TWAP when configured, so a single-slot spike cannot move them
+    let price = if v.twap_window_sec > 0 {
+        history
+            .and_then(|h| h.twap(now, v.twap_window_sec))
+            .ok_or(VaultError::InsufficientPriceHistory)?
+    } else {
+        median
+    };
+    Ok(Some((price, used)))
+}
+
+/// Pause, emergency and whitelist gates shared by every deposit.
+fn check_deposit_allowed(v: &Vault, user: &Pubkey) -> Result<()> {
+    require!(!v.paused, VaultError::VaultPaused);
+    require!(!v.emergency_withdraw_only, VaultError::EmergencyMode);
+    if !v.deposit_whitelist.is_empty() {
+        require!(v.deposit_whitelist.contains(user), VaultError::NotWhitelisted);
+    }
+    Ok(())
+}
+
+fn check_max_total(
+    v: &Vault,
+    base_vault: &TokenAccount,
+    quote_vault: &TokenAccount,
+    base_in: u64,
+    quote_in: u64,
+) -> Result<()> {
+    if v.max_total_deposit > 0 {
         require!(
-            now.checked_sub(v.last_rebalance_ts).unwrap() >= v.rebalance_cooldown_sec,
-            VaultError::CooldownNotPassed
+            base_vault.amount.checked_add(base_in).unwrap() <= v.max_total_deposit
+                && quote_vault.amount.checked_add(quote_in).unwrap() <= v.max_total_deposit,
+            VaultError::AboveMaxTotal
         );
-        require!(is_admin(v, ctx.accounts.authority.key), VaultError::Unauthorized);
+    }
+    Ok(())
+}
 
-        let band = current_price.checked_div(v.band_size_bp as u64 * 100).unwrap_or(0);
-        let old_band = v.last_band;
-        if band != old_band {
-            // TODO: CPI remove liquidity at old_band
-            // TODO: CPI add liquidity at band
-
-            v.accrued_fee_base = 0;
-            v.accrued_fee_quote = 0;
-            v.last_band = band;
-            v.last_rebalance_ts = now;
-            emit!(RebalanceEvent {
-                old_band,
-                new_band: band,
-                timestamp: now,
-            });
+/// Unwinds the holder's share of the venue position, burns `share_amount`
+/// and returns the base and quote they are owed net of the withdrawal fee.
+/// Venue accounts are read from the head of `remaining`.
+fn redeem_shares<'info>(
+    accounts: &mut Withdraw<'info>,
+    remaining: &[AccountInfo<'info>],
+    share_amount: u64,
+    price: Option<u64>,
+) -> Result<(u64, u64)> {
+    let vault_ref = &accounts.vault;
+    require!(!vault_ref.paused, VaultError::VaultPaused);
+    if !vault_ref.emergency_withdraw_only && !vault_ref.withdraw_whitelist.is_empty() {
+        require!(
+            vault_ref.withdraw_whitelist.contains(&accounts.user.key()),
+            VaultError::NotWhitelisted
+        );
+    }
+
+    accrue_management_fee(
+        &mut accounts.vault,
+        &mut accounts.share_mint,
+        &accounts.treasury_share_ata,
+        &accounts.token_program,
+        Clock::get()?.unix_timestamp,
+    )?;
+
+    // the holder's claim on every holding, before anything is unwound
+    let supply = accounts.share_mint.supply;
+    let (deployed, used) = venue::deployed_amounts(&accounts.vault, remaining, price)?;
+    let nav = {
+        let v = &accounts.vault;
+        Nav::new(
+            (accounts.base_vault.amount, accounts.quote_vault.amount),
+            (v.accrued_fee_base, v.accrued_fee_quote),
+            deployed,
+        )
+        .ok_or(VaultError::MathOverflow)?
+    };
+    let (ba, qa) = nav.claim(share_amount, supply).ok_or(VaultError::MathOverflow)?;
+
+    // unwind the holder's share of the deployed position into the vault
+    if venue::has_open_position(&accounts.vault) || deployed != (0, 0) {
+        let vault_info = accounts.vault.to_account_info();
+        let base_vault_info = accounts.base_vault.to_account_info();
+        let quote_vault_info = accounts.quote_vault.to_account_info();
+        let token_program_info = accounts.token_program.to_account_info();
+        let v = &mut accounts.vault;
+        let base_mint = v.base_mint;
+        let quote_mint = v.quote_mint;
+        let bump = [v.bump];
+        let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+        venue::reduce_liquidity(
+            v,
+            &VenueAccounts {
+                vault:         vault_info,
+                base_vault:    base_vault_info,
+                quote_vault:   quote_vault_info,
+                token_program: token_program_info,
+                remaining:     &remaining[used..],
+                signer_seeds:  &[seeds],
+            },
+            share_amount,
+            supply,
+        )?;
+        accounts.base_vault.reload()?;
+        accounts.quote_vault.reload()?;
+    }
+
+    // the venue rounds against the vault, so never pay out fees owed to the treasury
+    let vault_ref = &accounts.vault;
+    let ba = ba.min(accounts.base_vault.amount.saturating_sub(vault_ref.accrued_fee_base));
+    let qa = qa.min(accounts.quote_vault.amount.saturating_sub(vault_ref.accrued_fee_quote));
+    let fee_b = ba
+        .checked_mul(vault_ref.withdraw_fee_bps as u64)
+        .unwrap()
+        .checked_div(10_000)
+        .unwrap();
+    let fee_q = qa
+        .checked_mul(vault_ref.withdraw_fee_bps as u64)
+        .unwrap()
+        .checked_div(10_000)
+        .unwrap();
+    let nb = ba.checked_sub(fee_b).unwrap();
+    let nq = qa.checked_sub(fee_q).unwrap();
+
+    token::burn(accounts.burn_shares_ctx(), share_amount)?;
+
+    // withdrawal fees are owed to the treasury until swept
+    {
+        let vault = &mut accounts.vault;
+        vault.accrued_fee_base = vault.accrued_fee_base.checked_add(fee_b).unwrap();
+        vault.accrued_fee_quote = vault.accrued_fee_quote.checked_add(fee_q).unwrap();
+    }
+
+    // less the referrer's share, which stays in the accrued fees until claimed
+    let referral_info = accounts.referral.to_account_info();
+    if let Some(mut referral) = referral::load(&referral_info)?.filter(|r| r.has_referrer()) {
+        let vault = &mut accounts.vault;
+        referral.settle(vault.referral_fee_index).ok_or(VaultError::MathOverflow)?;
+        let burned = share_amount.min(referral.shares);
+        referral.shares -= burned;
+        vault.referred_shares = vault.referred_shares.saturating_sub(burned);
+        let cut_b = fees::protocol_cut(fee_b, vault.referral_share_bps);
+        let cut_q = fees::protocol_cut(fee_q, vault.referral_share_bps);
+        referral.owed_base = referral.owed_base.checked_add(cut_b).ok_or(VaultError::MathOverflow)?;
+        referral.owed_quote = referral.owed_quote.checked_add(cut_q).ok_or(VaultError::MathOverflow)?;
+        vault.referral_owed_base = vault.referral_owed_base.checked_add(cut_b).ok_or(VaultError::MathOverflow)?;
+        vault.referral_owed_quote = vault.referral_owed_quote.checked_add(cut_q).ok_or(VaultError::MathOverflow)?;
+        referral::store(&referral, &referral_info)?;
+    }
+
+    Ok((nb, nq))
+}
+
+/// Mints the management fee streamed since `last_fee_accrual_ts` to the
+/// treasury and reloads the share supply. The clock only moves on once a
+/// share is owed, so frequent accruals do not round the fee away.
+fn accrue_management_fee<'info>(
+    v: &mut Account<'info, Vault>,
+    share_mint: &mut Account<'info, Mint>,
+    treasury_share_ata: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    now: i64,
+) -> Result<()> {
+    let elapsed = (now as u64).saturating_sub(v.last_fee_accrual_ts);
+    let fee_shares = fees::management_fee_shares(share_mint.supply, v.management_fee_bps, elapsed)
+        .ok_or(VaultError::MathOverflow)?;
+    if fee_shares == 0 {
+        if v.management_fee_bps == 0 || share_mint.supply == 0 {
+            v.last_fee_accrual_ts = now as u64;
         }
-        Ok(())
+        return Ok(());
+    }
+    let base_mint = v.base_mint;
+    let quote_mint = v.quote_mint;
+    let bump = [v.bump];
+    let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+    token::mint_to(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            MintTo {
+                mint:      share_mint.to_account_info(),
+                to:        treasury_share_ata.to_account_info(),
+                authority: v.to_account_info(),
+            },
+            &[seeds],
+        ),
+        fee_shares,
+    )?;
+    share_mint.reload()?;
+    v.last_fee_accrual_ts = now as u64;
+    emit!(ManagementFeeAccrued { fee_shares, elapsed, timestamp: now as u64 });
+    Ok(())
+}
+
+/// Mints the performance fee on NAV per share above `high_water_mark` to
+/// the treasury and moves the mark. Referrers' part of it goes to the
+/// referral share escrow.
+fn charge_performance_fee<'info>(
+    v: &mut Account<'info, Vault>,
+    share_mint: &mut Account<'info, Mint>,
+    treasury_share_ata: &Account<'info, TokenAccount>,
+    referral_share_vault: Option<&Account<'info, TokenAccount>>,
+    token_program: &Program<'info, Token>,
+    nav_value: u64,
+    now: u64,
+) -> Result<()> {
+    let (fee_shares, high_water_mark) =
+        fees::performance_fee(nav_value, share_mint.supply, v.high_water_mark, v.perf_fee_bps)
+            .ok_or(VaultError::MathOverflow)?;
+    if fee_shares > 0 {
+        let (referral_shares, index_increase) = if v.referral_share_vault == Pubkey::default() {
+            (0, 0)
+        } else {
+            referral::referral_fee_shares(fee_shares, v.referred_shares, share_mint.supply, v.referral_share_bps)
+                .ok_or(VaultError::MathOverflow)?
+        };
+        let base_mint = v.base_mint;
+        let quote_mint = v.quote_mint;
+        let bump = [v.bump];
+        let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
+        let mint_to = |to: AccountInfo<'info>, amount: u64| {
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    MintTo {
+                        mint:      share_mint.to_account_info(),
+                        to,
+                        authority: v.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                amount,
+            )
+        };
+        mint_to(treasury_share_ata.to_account_info(), fee_shares - referral_shares)?;
+        if referral_shares > 0 {
+            let escrow = referral_share_vault.ok_or(VaultError::InvalidReferral)?;
+            mint_to(escrow.to_account_info(), referral_shares)?;
+        }
+        v.referral_fee_index = v.referral_fee_index.checked_add(index_increase).ok_or(VaultError::MathOverflow)?;
+        emit!(PerformanceFeeCharged {
+            nav_per_share: nav::nav_per_share(nav_value, share_mint.supply),
+            old_high_water_mark: v.high_water_mark,
+            new_high_water_mark: high_water_mark,
+            fee_shares,
+            referral_shares,
+            timestamp: now,
+        });
+        share_mint.reload()?;
     }
+    v.high_water_mark = high_water_mark;
+    Ok(())
+}
 
-    pub fn sweep_fees(ctx: Context<SweepFees>) -> Result<()> {
-        let vault_ref = &ctx.accounts.vault;
-        require!(is_admin(vault_ref, ctx.accounts.authority.key), VaultError::Unauthorized);
+/// Rejects a user instruction landing after its `deadline` (unix seconds).
+fn check_deadline(deadline: Option<i64>, now: i64) -> Result<()> {
+    if let Some(deadline) = deadline {
+        require!(now <= deadline, VaultError::DeadlineExceeded);
+    }
+    Ok(())
+}
 
-        // snapshot fees & seeds
-        let fb = vault_ref.accrued_fee_base;
-        let fq = vault_ref.accrued_fee_quote;
-        let bump = vault_ref.bump;
-        let base_mint = vault_ref.base_mint;
-        let quote_mint = vault_ref.quote_mint;
+/// Median of the vault's price sources, read from the head of `remaining`.
+/// Returns the price and the number of accounts consumed.
+fn oracle_price(
+    v: &Vault,
+    config: Option<&OracleConfig>,
+    remaining: &[AccountInfo],
+    now: i64,
+) -> Result<(u64, usize)> {
+    require!(
+        config.is_some() == (v.oracle_config != Pubkey::default()),
+        VaultError::InvalidOracleConfig
+    );
+    let (prices, used) = oracle::read_prices(v, config, remaining, now, false)?;
+    Ok((oracle::median(&prices), used))
+}
 
-        if fb > 0 {
-            token::transfer(
-                ctx.accounts
-                    .transfer_fee_base_ctx()
-                    .with_signer(&[&[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]]]),
-                fb,
-            )?;
-        }
-        if fq > 0 {
-            token::transfer(
-                ctx.accounts
-                    .transfer_fee_quote_ctx()
-                    .with_signer(&[&[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]]]),
-                fq,
-            )?;
-        }
+/// Holdings behind the vault's shares, positions split at `price`; venue
+/// accounts are read from the head of `remaining`.
+fn vault_nav(
+    v: &Vault,
+    base_vault: &TokenAccount,
+    quote_vault: &TokenAccount,
+    remaining: &[AccountInfo],
+    price: u64,
+) -> Result<Nav> {
+    let (deployed, _) = venue::deployed_amounts(v, remaining, Some(price))?;
+    let nav = Nav::new(
+        (base_vault.amount, quote_vault.amount),
+        (v.accrued_fee_base, v.accrued_fee_quote),
+        deployed,
+    )
+    .ok_or(VaultError::MathOverflow)?;
+    Ok(nav)
+}
 
-        // clear accrued fees
-        let mut vault = &mut ctx.accounts.vault;
-        vault.accrued_fee_base = 0;
-        vault.accrued_fee_quote = 0;
+/// Moves liquidity to the band of `price` once the vault's rebalance mode
+/// says it is due, first charging the performance fee and swapping toward
+/// the target ratio when a swap leg is given. `remaining` starts with the
+/// venue's NAV accounts (see `venue::deployed_amounts`). Returns whether it
+/// moved.
+#[allow(clippy::too_many_arguments)]
+fn rebalance_to_price<'info>(
+    v: &mut Account<'info, Vault>,
+    base_vault: &Account<'info, TokenAccount>,
+    quote_vault: &Account<'info, TokenAccount>,
+    share_mint: &mut Account<'info, Mint>,
+    treasury_share_ata: &Account<'info, TokenAccount>,
+    referral_share_vault: Option<&Account<'info, TokenAccount>>,
+    token_program: &Program<'info, Token>,
+    remaining: &[AccountInfo<'info>],
+    history: Option<&PriceHistory>,
+    swap_leg: Option<&SwapLeg>,
+    price: u64,
+    now: u64,
+) -> Result<bool> {
+    require!(!v.paused, VaultError::VaultPaused);
+    require!(!v.emergency_withdraw_only, VaultError::EmergencyMode);
+    accrue_management_fee(v, share_mint, treasury_share_ata, token_program, now as i64)?;
+    require!(
+        now.checked_sub(v.last_rebalance_ts).unwrap() >= v.rebalance_cooldown_sec,
+        VaultError::CooldownNotPassed
+    );
 
-        Ok(())
+    let band_bp = strategy::effective_band_bp(
+        v.band_size_bp,
+        v.band_min_bp,
+        v.band_max_bp,
+        v.band_vol_multiplier_bps,
+        history.and_then(|h| h.ewma_volatility_bps()),
+    );
+    let band = math::band_of_price(price, band_bp).ok_or(VaultError::InvalidPrice)?;
+    let old_band = v.last_band;
+    // inventory is what the vault holds for share holders, idle or deployed
+    let (deployed, used) = venue::deployed_amounts(v, remaining, Some(price))?;
+    let remaining = &remaining[used..];
+    let accrued = (v.accrued_fee_base, v.accrued_fee_quote);
+    let inventory =
+        Nav::new((base_vault.amount, quote_vault.amount), accrued, deployed).ok_or(VaultError::MathOverflow)?;
+    let base_value = math::base_value_in_quote(inventory.base, price, v.base_decimals, v.quote_decimals)
+        .ok_or(VaultError::MathOverflow)?;
+    let trigger = TriggerState {
+        band,
+        old_band,
+        band_size_bp:         v.effective_band_bp,
+        new_band_size_bp:     band_bp,
+        price,
+        last_rebalance_price: v.last_rebalance_price,
+        now,
+        last_rebalance_ts:    v.last_rebalance_ts,
+        base_value,
+        quote_amount:         inventory.quote,
+        target_base_bps:      v.target_base_bps,
+    };
+    if !v.rebalance_mode.should_rebalance(&trigger)? {
+        return Ok(false);
     }
-}
+    v.effective_band_bp = band_bp;
+    let vault_info = v.to_account_info();
+    let base_vault_info = base_vault.to_account_info();
+    let quote_vault_info = quote_vault.to_account_info();
+    let base_mint = v.base_mint;
+    let quote_mint = v.quote_mint;
+    let bump = [v.bump];
+    let seeds: &[&[u8]] = &[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &bump];
 
-// -- Helpers, State, Events, Errors, Contexts (unchanged) --
+    // performance fee on NAV gained since the last charge, before anything moves
+    let nav_value = base_value.checked_add(inventory.quote).ok_or(VaultError::MathOverflow)?;
+    charge_performance_fee(v, share_mint, treasury_share_ata, referral_share_vault, token_program, nav_value, now)?;
 
-fn is_admin(v: &Vault, key: &Pubkey) -> bool {
-    *key == v.authority || v.admins.contains(key)
+    let (swap_result, remaining) = match swap_leg {
+        Some(leg) => swap::swap_to_ratio(
+            v,
+            &vault_info,
+            &base_vault_info,
+            &quote_vault_info,
+            remaining,
+            leg,
+            price,
+            &[seeds],
+        )?,
+        None => (SwapResult::default(), remaining),
+    };
+
+    // lopsided inventory moves the ladder so the excess side quotes closer to the price
+    let inventory = Nav::new(
+        (token::accessor::amount(&base_vault_info)?, token::accessor::amount(&quote_vault_info)?),
+        accrued,
+        deployed,
+    )
+    .ok_or(VaultError::MathOverflow)?;
+    let base_value = math::base_value_in_quote(inventory.base, price, v.base_decimals, v.quote_decimals)
+        .ok_or(VaultError::MathOverflow)?;
+    let inventory_skew_bps = strategy::inventory_skew_bps(base_value, inventory.quote, v.target_base_bps);
+    let center = band
+        + strategy::skew_band_offset(inventory_skew_bps, v.max_inventory_skew_bps, v.ladder.half_width.max(1) as i64);
+
+    // solvers can finish the job at a decaying price; the offered side is held back from the venue
+    if v.auction.is_some_and(|a| !a.is_live(now)) {
+        v.auction = None;
+    }
+    let auction_size = if v.auction_duration_sec > 0
+        && v.max_inventory_skew_bps > 0
+        && inventory_skew_bps.unsigned_abs() > v.max_inventory_skew_bps as u64
+    {
+        // sized from everything held, including bands about to be pulled
+        Some(
+            auction::rebalance_amount(inventory.base, base_value, inventory.quote, v.target_base_bps, v.max_swap_notional)
+                .ok_or(VaultError::MathOverflow)?,
+        )
+    } else {
+        None
+    };
+    let accounts = VenueAccounts {
+        vault:         vault_info,
+        base_vault:    base_vault_info.clone(),
+        quote_vault:   quote_vault_info.clone(),
+        token_program: token_program.to_account_info(),
+        remaining,
+        signer_seeds:  &[seeds],
+    };
+    venue::move_liquidity(v, &accounts, center, |v| {
+        let Some((sell_base, amount)) = auction_size else {
+            return Ok(());
+        };
+        // only what is idle once the withdraw leg has run can be held back
+        let idle = if sell_base {
+            token::accessor::amount(&base_vault_info)?.saturating_sub(v.accrued_fee_base)
+        } else {
+            token::accessor::amount(&quote_vault_info)?.saturating_sub(v.accrued_fee_quote)
+        };
+        let amount = amount.min(idle);
+        // nothing to offer, e.g. `max_swap_notional` is 0
+        if amount == 0 {
+            return Ok(());
+        }
+        let posted = RebalanceAuction::open(
+            sell_base,
+            amount,
+            price,
+            v.auction_premium_bps,
+            v.auction_discount_bps,
+            now,
+            v.auction_duration_sec,
+        )
+        .ok_or(VaultError::MathOverflow)?;
+        emit!(AuctionPosted {
+            sell_base,
+            amount,
+            start_price: posted.start_price,
+            end_price: posted.end_price,
+            end_ts: posted.end_ts,
+        });
+        v.auction = Some(posted);
+        Ok(())
+    })?;
+
+    v.last_band = band;
+    v.last_rebalance_ts = now;
+    v.last_rebalance_price = price;
+    emit!(RebalanceEvent {
+        old_band,
+        new_band: band,
+        center_band: center,
+        effective_band_bp: band_bp,
+        inventory_skew_bps,
+        swap_base_delta: swap_result.base_delta,
+        swap_quote_delta: swap_result.quote_delta,
+        timestamp: now,
+    });
+    Ok(true)
 }
 
 #[account]
@@ -352,34 +1658,238 @@ pub struct Vault {
     pub treasury:               Pubkey,
     pub base_mint:              Pubkey,
     pub quote_mint:             Pubkey,
+    pub base_decimals:          u8,
+    pub quote_decimals:         u8,
     pub share_mint:             Pubkey,
     pub base_vault:             Pubkey,
     pub quote_vault:            Pubkey,
     pub fee_bps:                u16,
     pub withdraw_fee_bps:       u16,
+    pub zap_fee_bps:            u16,
+    pub perf_fee_bps:           u16,
+    pub management_fee_bps:     u16,
     pub band_size_bp:           u16,
+    pub band_min_bp:            u16,
+    pub band_max_bp:            u16,
+    pub band_vol_multiplier_bps: u16,
+    pub effective_band_bp:      u16,
     pub min_deposit_amount:     u64,
     pub max_total_deposit:      u64,
-    pub last_band:              u64,
+    pub last_band:              i64,
     pub rebalance_cooldown_sec: u64,
     pub last_rebalance_ts:      u64,
-    pub rebalance_mode:         u8,
+    pub last_rebalance_price:   u64,
+    pub rebalance_mode:         RebalanceMode,
     pub paused:                 bool,
     pub emergency_withdraw_only: bool,
     pub accrued_fee_base:       u64,
     pub accrued_fee_quote:      u64,
+    pub high_water_mark:        u64,
+    pub last_fee_accrual_ts:    u64,
+    pub min_harvest_value:      u64,
+    pub fee_recipients:         Vec<FeeRecipient>,
+    pub referral_share_bps:     u16,
+    pub referral_share_vault:   Pubkey,
+    pub referred_shares:        u64,
+    pub referral_fee_index:     u128,
+    pub referral_owed_base:     u64,
+    pub referral_owed_quote:    u64,
     pub deposit_whitelist:      Vec<Pubkey>,
     pub withdraw_whitelist:     Vec<Pubkey>,
+    pub venue:                  VenueKind,
+    pub venue_program:          Pubkey,
+    pub venue_market:           Pubkey,
+    pub positions:              Vec<LpPosition>,
+    pub ladder:                 Ladder,
+    pub target_base_bps:        u16,
+    pub max_inventory_skew_bps: u16,
+    pub swap_program:           Pubkey,
+    pub max_swap_slippage_bps:  u16,
+    pub max_swap_notional:      u64,
+    pub auction_duration_sec:   u64,
+    pub auction_premium_bps:    u16,
+    pub auction_discount_bps:   u16,
+    pub auction:                Option<RebalanceAuction>,
+    pub clob:                   ClobState,
+    pub oracle_feed_id:         [u8; 32],
+    pub oracle_max_age_sec:     u64,
+    pub oracle_max_conf_bps:    u16,
+    pub oracle_config:          Pubkey,
+    pub price_history:          Pubkey,
+    pub twap_window_sec:        u64,
+    pub keeper_bounty_lamports: u64,
+    pub keeper_budget_lamports: u64,
+    pub keeper_epoch_cap_lamports: u64,
+    pub keeper_epoch:           u64,
+    pub keeper_paid_this_epoch: u64,
     pub bump:                   u8,
 }
 
+impl Vault {
+    /// Allocated once; the vectors are bounded so the largest vault still
+    /// fits (see the test below).
+    pub const SPACE: usize = 4096;
+}
+
+#[account]
+pub struct OracleConfig {
+    pub vault:              Pubkey,
+    pub feeds:              Vec<OracleFeed>,
+    pub max_divergence_bps: u16,
+    pub bump:               u8,
+}
+
+impl OracleConfig {
+    pub const SPACE: usize = 8 + 32 + 4 + oracle::MAX_ORACLE_FEEDS * (1 + 32) + 2 + 1;
+}
+
+#[account]
+pub struct PriceHistory {
+    pub vault:            Pubkey,
+    pub min_interval_sec: u64,
+    pub head:             u16,
+    pub len:              u16,
+    pub observations:     [PriceObservation; PRICE_HISTORY_LEN],
+    pub bump:             u8,
+}
+
+impl PriceHistory {
+    pub const SPACE: usize = 8 + 32 + 8 + 2 + 2 + PRICE_HISTORY_LEN * (8 + 8 + 16) + 1;
+}
+
+/// Who referred a user, recorded on their first deposit, and what the
+/// referrer has earned from them.
+#[account]
+pub struct Referral {
+    pub vault:       Pubkey,
+    pub user:        Pubkey,
+    pub referrer:    Pubkey,
+    pub shares:      u64,
+    pub fee_index:   u128,
+    pub owed_base:   u64,
+    pub owed_quote:  u64,
+    pub owed_shares: u64,
+    pub bump:        u8,
+}
+
+#[event]
+pub struct DepositEvent {
+    pub user:         Pubkey,
+    pub base_used:    u64,
+    pub quote_used:   u64,
+    pub base_unused:  u64,
+    pub quote_unused: u64,
+    pub shares:       u64,
+    pub timestamp:    u64,
+}
+
 #[event]
 pub struct RebalanceEvent {
-    pub old_band:   u64,
-    pub new_band:   u64,
+    pub old_band:           i64,
+    pub new_band:           i64,
+    pub center_band:        i64,
+    pub effective_band_bp:  u16,
+    pub inventory_skew_bps: i64,
+    pub swap_base_delta:    i64,
+    pub swap_quote_delta:   i64,
+    pub timestamp:          u64,
+}
+
+#[event]
+pub struct PerformanceFeeCharged {
+    pub nav_per_share:       u64,
+    pub old_high_water_mark: u64,
+    pub new_high_water_mark: u64,
+    pub fee_shares:          u64,
+    pub referral_shares:     u64,
+    pub timestamp:           u64,
+}
+
+#[event]
+pub struct ManagementFeeAccrued {
+    pub fee_shares: u64,
+    pub elapsed:    u64,
     pub timestamp:  u64,
 }
 
+#[event]
+pub struct Harvested {
+    pub base:             u64,
+    pub quote:            u64,
+    pub protocol_base:    u64,
+    pub protocol_quote:   u64,
+    pub compounded_base:  u64,
+    pub compounded_quote: u64,
+    pub value:            u64,
+    pub timestamp:        u64,
+}
+
+#[event]
+pub struct FeePaid {
+    pub recipient: Pubkey,
+    pub base:      u64,
+    pub quote:     u64,
+}
+
+#[event]
+pub struct ReferralRewardsClaimed {
+    pub referrer: Pubkey,
+    pub user:     Pubkey,
+    pub base:     u64,
+    pub quote:    u64,
+    pub shares:   u64,
+}
+
+#[event]
+pub struct PoolFeesCollected {
+    pub base:           u64,
+    pub quote:          u64,
+    pub protocol_base:  u64,
+    pub protocol_quote: u64,
+}
+
+#[event]
+pub struct BandAllocationChanged {
+    pub band:           i64,
+    pub old_weight_bps: u16,
+    pub new_weight_bps: u16,
+}
+
+#[event]
+pub struct AuctionPosted {
+    pub sell_base:   bool,
+    pub amount:      u64,
+    pub start_price: u64,
+    pub end_price:   u64,
+    pub end_ts:      u64,
+}
+
+#[event]
+pub struct AuctionFilled {
+    pub solver:    Pubkey,
+    pub sell_base: bool,
+    pub amount:    u64,
+    pub cost:      u64,
+    pub price:     u64,
+    pub remaining: u64,
+}
+
+#[event]
+pub struct KeeperPaid {
+    pub keeper: Pubkey,
+    pub amount: u64,
+    pub epoch:  u64,
+}
+
+#[event]
+pub struct OracleDivergence {
+    pub feed_index:     u8,
+    pub price:          u64,
+    pub median:         u64,
+    pub divergence_bps: u64,
+    pub timestamp:      u64,
+}
+
 #[error_code]
 pub enum VaultError {
     #[msg("Vault is paused")] VaultPaused,
@@ -389,6 +1899,51 @@ pub enum VaultError {
     #[msg("Above maximum total deposit")] AboveMaxTotal,
     #[msg("Cooldown not passed")] CooldownNotPassed,
     #[msg("Unauthorized")] Unauthorized,
+    #[msg("Invalid venue accounts")] InvalidVenueAccounts,
+    #[msg("Venue position still open")] PositionOpen,
+    #[msg("Math overflow")] MathOverflow,
+    #[msg("Invalid quote config")] InvalidQuoteConfig,
+    #[msg("Invalid oracle account")] InvalidOracle,
+    #[msg("Invalid oracle config")] InvalidOracleConfig,
+    #[msg("Oracle feed does not match vault")] OracleFeedMismatch,
+    #[msg("Oracle price is stale")] StaleOracle,
+    #[msg("Oracle confidence interval too wide")] OracleConfidenceTooWide,
+    #[msg("Price recorded too soon")] PriceRecordedTooSoon,
+    #[msg("Not enough price history")] InsufficientPriceHistory,
+    #[msg("Invalid rebalance mode")] InvalidRebalanceMode,
+    #[msg("Price has not crossed the band")] BandNotCrossed,
+    #[msg("Rebalance interval not elapsed")] IntervalNotElapsed,
+    #[msg("Price move below volatility threshold")] VolatilityBelowThreshold,
+    #[msg("Inventory within skew bounds")] InventoryWithinBounds,
+    #[msg("Manual mode requires an admin rebalance")] ManualRebalanceOnly,
+    #[msg("Invalid band range")] InvalidBandRange,
+    #[msg("Band size must be non-zero")] InvalidBandSize,
+    #[msg("Price must be non-zero")] InvalidPrice,
+    #[msg("Invalid ladder config")] InvalidLadder,
+    #[msg("Invalid inventory target")] InvalidInventoryTarget,
+    #[msg("Invalid swap config")] InvalidSwapConfig,
+    #[msg("Swaps are disabled")] SwapDisabled,
+    #[msg("Swap does not move inventory toward target")] InvalidSwap,
+    #[msg("Swap notional above cap")] SwapNotionalExceeded,
+    #[msg("Swap slippage above limit")] SwapSlippageExceeded,
+    #[msg("Invalid auction config")] InvalidAuctionConfig,
+    #[msg("No live rebalance auction")] NoLiveAuction,
+    #[msg("Invalid auction fill amount")] InvalidAuctionFill,
+    #[msg("Auction cost above maximum")] AuctionCostExceeded,
+    #[msg("First deposit below minimum initial liquidity")] BelowMinInitialDeposit,
+    #[msg("Transaction deadline passed")] DeadlineExceeded,
+    #[msg("Shares minted below minimum")] SharesOutBelowMinimum,
+    #[msg("Base received below minimum")] BaseOutBelowMinimum,
+    #[msg("Quote received below minimum")] QuoteOutBelowMinimum,
+    #[msg("Invalid zap fee")] InvalidZapFee,
+    #[msg("Fee above 100%")] InvalidFee,
+    #[msg("Harvest below minimum value")] HarvestBelowMinimum,
+    #[msg("Invalid fee recipients")] InvalidFeeRecipients,
+    #[msg("Referrer cannot be the depositor")] InvalidReferrer,
+    #[msg("Referrer can only be named on the first deposit")] ReferrerAlreadyFixed,
+    #[msg("Invalid referral account")] InvalidReferral,
+    #[msg("Referral share escrow not initialized")] ReferralsNotInitialized,
+    #[msg("Not enough idle inventory for a single-sided withdrawal")] InsufficientZapLiquidity,
 }
 
 #[derive(Accounts)]
@@ -399,7 +1954,7 @@ pub struct Initialize<'info> {
         payer = authority,
         seeds = [b"vault", base_mint.key().as_ref(), quote_mint.key().as_ref()],
         bump,
-        space = 4096
+        space = Vault::SPACE
     )]
     pub vault:         Account<'info, Vault>,
     #[account(mut)]    pub authority:   Signer<'info>,
@@ -450,6 +2005,90 @@ pub struct UpdateFee<'info> {
     pub authority:     Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct SetManagementFee<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    pub authority:     Signer<'info>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitReferralShareVault<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    #[account(mut)]    pub authority: Signer<'info>,
+    #[account(seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"referral_shares", vault.key().as_ref()],
+        bump,
+        token::mint = share_mint,
+        token::authority = vault
+    )]
+    pub referral_share_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitOracleConfig<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    #[account(mut)]    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"oracle_config", vault.key().as_ref()],
+        bump,
+        space = OracleConfig::SPACE
+    )]
+    pub oracle_config: Account<'info, OracleConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateOracleConfig<'info> {
+    pub vault:         Account<'info, Vault>,
+    pub authority:     Signer<'info>,
+    #[account(mut, seeds = [b"oracle_config", vault.key().as_ref()], bump = oracle_config.bump, has_one = vault)]
+    pub oracle_config: Account<'info, OracleConfig>,
+}
+
+#[derive(Accounts)]
+pub struct InitPriceHistory<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    #[account(mut)]    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"price_history", vault.key().as_ref()],
+        bump,
+        space = PriceHistory::SPACE
+    )]
+    pub price_history: Account<'info, PriceHistory>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordPrice<'info> {
+    pub vault:         Account<'info, Vault>,
+    #[account(mut, seeds = [b"price_history", vault.key().as_ref()], bump = price_history.bump, has_one = vault)]
+    pub price_history: Account<'info, PriceHistory>,
+    #[account(address = vault.oracle_config)]
+    pub oracle_config: Option<Account<'info, OracleConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct InitOpenOrders<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    #[account(mut)]    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct ModifyAdmins<'info> {
     #[account(mut)]    pub vault:     Account<'info, Vault>,
@@ -465,11 +2104,11 @@ pub struct ModifyWhitelist<'info> {
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     #[account(mut)]    pub vault:         Account<'info, Vault>,
-    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
     pub share_mint:     Account<'info, Mint>,
-    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
     pub base_vault:     Account<'info, TokenAccount>,
-    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
     pub quote_vault:    Account<'info, TokenAccount>,
     #[account(mut)]    pub user:          Signer<'info>,
     #[account(mut, constraint = user_base_ata.mint == vault.base_mint)]
@@ -478,7 +2117,15 @@ pub struct Deposit<'info> {
     pub user_quote_ata: Account<'info, TokenAccount>,
     #[account(mut, constraint = user_share_ata.mint == vault.share_mint)]
     pub user_share_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    #[account(address = vault.oracle_config)]
+    pub oracle_config:  Option<Account<'info, OracleConfig>>,
+    /// CHECK: the user's referral PDA, created on their first deposit
+    #[account(mut, seeds = [b"referral", vault.key().as_ref(), user.key().as_ref()], bump)]
+    pub referral:       UncheckedAccount<'info>,
     pub token_program:  Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 impl<'info> Deposit<'info> {
@@ -503,16 +2150,91 @@ impl<'info> Deposit<'info> {
             authority: self.vault.to_account_info(),
         })
     }
+
+    fn accrue_management_fee(&mut self, now: i64) -> Result<()> {
+        accrue_management_fee(
+            &mut self.vault,
+            &mut self.share_mint,
+            &self.treasury_share_ata,
+            &self.token_program,
+            now,
+        )
+    }
+
+    /// Creates the user's referral PDA on their first deposit, fixing the
+    /// `referrer` named there (if any), and tracks the shares minted to
+    /// referred users.
+    fn record_referral(&mut self, referrer: Option<Pubkey>, shares: u64, bump: u8) -> Result<()> {
+        let info = self.referral.to_account_info();
+        let (mut referral, first) = match referral::load(&info)? {
+            Some(referral) => (referral, false),
+            None => {
+                let vault_key = self.vault.key();
+                let user_key = self.user.key();
+                referral::create(
+                    &info,
+                    &self.user.to_account_info(),
+                    &self.system_program.to_account_info(),
+                    &[b"referral", vault_key.as_ref(), user_key.as_ref(), &[bump]],
+                )?;
+                let referral = Referral {
+                    vault: vault_key,
+                    user: user_key,
+                    referrer: Pubkey::default(),
+                    shares: 0,
+                    fee_index: self.vault.referral_fee_index,
+                    owed_base: 0,
+                    owed_quote: 0,
+                    owed_shares: 0,
+                    bump,
+                };
+                (referral, true)
+            }
+        };
+        let referred = referral.record_deposit(first, referrer, shares, self.vault.referral_fee_index)?;
+        self.vault.referred_shares = self.vault.referred_shares.checked_add(referred).ok_or(VaultError::MathOverflow)?;
+        referral::store(&referral, &info)
+    }
+
+    /// Takes the `used` amounts, mints `shares` and emits `DepositEvent`.
+    fn settle(&mut self, used: (u64, u64), unused: (u64, u64), shares: u64, now: i64) -> Result<()> {
+        // transfer in
+        token::transfer(self.transfer_base_to_vault_ctx(), used.0)?;
+        token::transfer(self.transfer_quote_to_vault_ctx(), used.1)?;
+
+        // prepare seeds
+        let bump = self.vault.bump;
+        let base_mint = self.vault.base_mint;
+        let quote_mint = self.vault.quote_mint;
+
+        // mint shares
+        token::mint_to(
+            self.mint_shares_ctx()
+                .with_signer(&[&[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]]]),
+            shares,
+        )?;
+
+        emit!(DepositEvent {
+            user: self.user.key(),
+            base_used: used.0,
+            quote_used: used.1,
+            base_unused: unused.0,
+            quote_unused: unused.1,
+            shares,
+            timestamp: now as u64,
+        });
+        Ok(())
+    }
 }
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account(mut)]    pub vault:         Account<'info, Vault>,
-    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
     pub share_mint:     Account<'info, Mint>,
-    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
     pub base_vault:     Account<'info, TokenAccount>,
-    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
     pub quote_vault:    Account<'info, TokenAccount>,
     #[account(mut)]    pub user:          Signer<'info>,
     #[account(mut, constraint = user_base_ata.mint == vault.base_mint)]
@@ -521,6 +2243,14 @@ pub struct Withdraw<'info> {
     pub user_quote_ata: Account<'info, TokenAccount>,
     #[account(mut, constraint = user_share_ata.mint == vault.share_mint)]
     pub user_share_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    /// Only read by `withdraw_single`.
+    #[account(address = vault.oracle_config)]
+    pub oracle_config:  Option<Account<'info, OracleConfig>>,
+    /// CHECK: the user's referral PDA, created on their first deposit
+    #[account(mut, seeds = [b"referral", vault.key().as_ref(), user.key().as_ref()], bump)]
+    pub referral:       UncheckedAccount<'info>,
     pub token_program:  Program<'info, Token>,
 }
 
@@ -548,27 +2278,200 @@ impl<'info> Withdraw<'info> {
     }
 }
 
+#[derive(Accounts)]
+pub struct GetNav<'info> {
+    pub vault:         Account<'info, Vault>,
+    #[account(seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:    Account<'info, TokenAccount>,
+    #[account(seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:   Account<'info, TokenAccount>,
+    #[account(address = vault.oracle_config)]
+    pub oracle_config: Option<Account<'info, OracleConfig>>,
+}
+
 #[derive(Accounts)]
 pub struct Rebalance<'info> {
     #[account(mut)]    pub vault:         Account<'info, Vault>,
     pub authority:     Signer<'info>,
-    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
     pub base_vault:    Account<'info, TokenAccount>,
-    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
     pub quote_vault:   Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    #[account(mut, address = vault.referral_share_vault)]
+    pub referral_share_vault: Option<Account<'info, TokenAccount>>,
+    #[account(address = vault.price_history)]
+    pub price_history: Option<Account<'info, PriceHistory>>,
     pub token_program: Program<'info, Token>,
     pub clock:         Sysvar<'info, Clock>,
 }
 
+#[derive(Accounts)]
+pub struct RebalanceOracle<'info> {
+    #[account(mut)]    pub vault:         Account<'info, Vault>,
+    pub authority:     Signer<'info>,
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:    Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:   Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    #[account(mut, address = vault.referral_share_vault)]
+    pub referral_share_vault: Option<Account<'info, TokenAccount>>,
+    #[account(address = vault.oracle_config)]
+    pub oracle_config: Option<Account<'info, OracleConfig>>,
+    #[account(address = vault.price_history)]
+    pub price_history: Option<Account<'info, PriceHistory>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CrankRebalance<'info> {
+    #[account(mut)]    pub vault:         Account<'info, Vault>,
+    #[account(mut)]    pub keeper:        Signer<'info>,
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:    Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:   Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    #[account(mut, address = vault.referral_share_vault)]
+    pub referral_share_vault: Option<Account<'info, TokenAccount>>,
+    #[account(address = vault.oracle_config)]
+    pub oracle_config: Option<Account<'info, OracleConfig>>,
+    #[account(address = vault.price_history)]
+    pub price_history: Option<Account<'info, PriceHistory>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundKeeperBudget<'info> {
+    #[account(mut)]    pub vault:          Account<'info, Vault>,
+    #[account(mut)]    pub funder:         Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FillRebalanceAuction<'info> {
+    #[account(mut)]    pub vault:          Account<'info, Vault>,
+    pub solver:          Signer<'info>,
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:      Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:     Account<'info, TokenAccount>,
+    #[account(mut, constraint = solver_base_ata.mint == vault.base_mint)]
+    pub solver_base_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = solver_quote_ata.mint == vault.quote_mint)]
+    pub solver_quote_ata: Account<'info, TokenAccount>,
+    pub token_program:   Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Harvest<'info> {
+    #[account(mut)]    pub vault:         Account<'info, Vault>,
+    pub keeper:        Signer<'info>,
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:    Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:   Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    #[account(mut, address = vault.referral_share_vault)]
+    pub referral_share_vault: Option<Account<'info, TokenAccount>>,
+    #[account(address = vault.oracle_config)]
+    pub oracle_config: Option<Account<'info, OracleConfig>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SettleFunds<'info> {
+    #[account(mut)]    pub vault:         Account<'info, Vault>,
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:    Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:   Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AccrueFees<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    #[account(mut, seeds = [b"share_mint", vault.key().as_ref()], bump)]
+    pub share_mint:    Account<'info, Mint>,
+    #[account(mut, constraint = treasury_share_ata.owner == vault.treasury && treasury_share_ata.mint == vault.share_mint)]
+    pub treasury_share_ata: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(mut)]    pub vault:     Account<'info, Vault>,
+    pub referrer:      Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"referral", vault.key().as_ref(), referral.user.as_ref()],
+        bump = referral.bump,
+        has_one = vault,
+        has_one = referrer @ VaultError::Unauthorized
+    )]
+    pub referral:      Account<'info, Referral>,
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
+    pub base_vault:    Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
+    pub quote_vault:   Account<'info, TokenAccount>,
+    #[account(mut, address = vault.referral_share_vault)]
+    pub referral_share_vault: Option<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = referrer_base_ata.mint == vault.base_mint)]
+    pub referrer_base_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = referrer_quote_ata.mint == vault.quote_mint)]
+    pub referrer_quote_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = referrer_share_ata.mint == vault.share_mint)]
+    pub referrer_share_ata: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> ClaimReferralRewards<'info> {
+    /// Transfers `amount` out of a vault-owned token account.
+    fn pay(&self, from: &Account<'info, TokenAccount>, to: &Account<'info, TokenAccount>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Ok(());
+        }
+        let v = &self.vault;
+        token::transfer(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: from.to_account_info(),
+                    to: to.to_account_info(),
+                    authority: v.to_account_info(),
+                },
+            )
+            .with_signer(&[&[b"vault", v.base_mint.as_ref(), v.quote_mint.as_ref(), &[v.bump]]]),
+            amount,
+        )
+    }
+}
+
 #[derive(Accounts)]
 pub struct SweepFees<'info> {
     #[account(mut)]    pub vault:             Account<'info, Vault>,
     pub authority:     Signer<'info>,
-    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"base_vault", vault.key().as_ref()], bump)]
     pub base_vault:        Account<'info, TokenAccount>,
     #[account(mut, constraint = treasury_base_ata.owner == vault.treasury && treasury_base_ata.mint == vault.base_mint)]
     pub treasury_base_ata: Account<'info, TokenAccount>,
-    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump = vault.bump)]
+    #[account(mut, seeds = [b"quote_vault", vault.key().as_ref()], bump)]
     pub quote_vault:       Account<'info, TokenAccount>,
     #[account(mut, constraint = treasury_quote_ata.owner == vault.treasury && treasury_quote_ata.mint == vault.quote_mint)]
     pub treasury_quote_ata:Account<'info, TokenAccount>,
@@ -590,4 +2493,149 @@ impl<'info> SweepFees<'info> {
             authority: self.vault.to_account_info(),
         })
     }
+
+    /// Pays the accrued fees to the treasury's token accounts.
+    fn pay_treasury(&self, fb: u64, fq: u64) -> Result<()> {
+        let vault_ref = &self.vault;
+        let bump = vault_ref.bump;
+        let base_mint = vault_ref.base_mint;
+        let quote_mint = vault_ref.quote_mint;
+
+        if fb > 0 {
+            token::transfer(
+                self.transfer_fee_base_ctx()
+                    .with_signer(&[&[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]]]),
+                fb,
+            )?;
+        }
+        if fq > 0 {
+            token::transfer(
+                self.transfer_fee_quote_ctx()
+                    .with_signer(&[&[b"vault", base_mint.as_ref(), quote_mint.as_ref(), &[bump]]]),
+                fq,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ladder::{BandAllocation, MAX_LADDER_HALF_WIDTH};
+
+    /// A vault with every list at its cap.
+    pub(crate) fn largest_vault() -> Vault {
+        let bands = 2 * MAX_LADDER_HALF_WIDTH as i64 + 1;
+        Vault {
+            authority: Pubkey::new_unique(),
+            admins: vec![Pubkey::new_unique()],
+            treasury: Pubkey::new_unique(),
+            base_mint: Pubkey::new_unique(),
+            quote_mint: Pubkey::new_unique(),
+            base_decimals: 9,
+            quote_decimals: 6,
+            share_mint: Pubkey::new_unique(),
+            base_vault: Pubkey::new_unique(),
+            quote_vault: Pubkey::new_unique(),
+            fee_bps: 0,
+            withdraw_fee_bps: 0,
+            zap_fee_bps: 0,
+            perf_fee_bps: 0,
+            management_fee_bps: 0,
+            band_size_bp: 5,
+            band_min_bp: 0,
+            band_max_bp: 0,
+            band_vol_multiplier_bps: 0,
+            effective_band_bp: 5,
+            min_deposit_amount: 0,
+            max_total_deposit: 0,
+            last_band: 0,
+            rebalance_cooldown_sec: 0,
+            last_rebalance_ts: 0,
+            last_rebalance_price: 0,
+            rebalance_mode: RebalanceMode::TimeInterval { interval_sec: 0 },
+            paused: false,
+            emergency_withdraw_only: false,
+            accrued_fee_base: 0,
+            accrued_fee_quote: 0,
+            high_water_mark: 0,
+            last_fee_accrual_ts: 0,
+            min_harvest_value: 0,
+            fee_recipients: vec![
+                FeeRecipient { recipient: Pubkey::new_unique(), bps: 2_000 };
+                fees::MAX_FEE_RECIPIENTS
+            ],
+            referral_share_bps: 0,
+            referral_share_vault: Pubkey::new_unique(),
+            referred_shares: 0,
+            referral_fee_index: 0,
+            referral_owed_base: 0,
+            referral_owed_quote: 0,
+            deposit_whitelist: Vec::new(),
+            withdraw_whitelist: Vec::new(),
+            venue: VenueKind::Whirlpool,
+            venue_program: Pubkey::new_unique(),
+            venue_market: Pubkey::new_unique(),
+            positions: (0..bands)
+                .map(|band| LpPosition {
+                    band,
+                    position: Pubkey::new_unique(),
+                    position_mint: Pubkey::new_unique(),
+                    funder: Pubkey::new_unique(),
+                    tick_lower: 0,
+                    tick_upper: 0,
+                    liquidity: 0,
+                })
+                .collect(),
+            ladder: Ladder {
+                shape: LadderShape::Skewed { bid_bps: 5_000 },
+                half_width: MAX_LADDER_HALF_WIDTH,
+                center: 0,
+                band_bp: 5,
+                allocations: (0..bands).map(|band| BandAllocation { band, weight_bps: 0 }).collect(),
+            },
+            target_base_bps: 5_000,
+            max_inventory_skew_bps: 0,
+            swap_program: Pubkey::new_unique(),
+            max_swap_slippage_bps: 0,
+            max_swap_notional: 0,
+            auction_duration_sec: 0,
+            auction_premium_bps: 0,
+            auction_discount_bps: 0,
+            auction: Some(RebalanceAuction {
+                sell_base: true,
+                remaining: 0,
+                start_ts: 0,
+                end_ts: 0,
+                start_price: 0,
+                end_price: 0,
+            }),
+            clob: ClobState {
+                open_order_ids: (0..2 * venue::MAX_QUOTE_LEVELS as u64).collect(),
+                ..ClobState::default()
+            },
+            oracle_feed_id: [0; 32],
+            oracle_max_age_sec: 0,
+            oracle_max_conf_bps: 0,
+            oracle_config: Pubkey::new_unique(),
+            price_history: Pubkey::new_unique(),
+            twap_window_sec: 0,
+            keeper_bounty_lamports: 0,
+            keeper_budget_lamports: 0,
+            keeper_epoch_cap_lamports: 0,
+            keeper_epoch: 0,
+            keeper_paid_this_epoch: 0,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn largest_vault_fits_its_account() {
+        let vault = largest_vault();
+        let mut data = Vec::new();
+        vault.try_serialize(&mut data).unwrap();
+        // leaves room for admins and whitelists
+        assert!(data.len() + 32 * 32 <= Vault::SPACE, "{} bytes", data.len());
+    }
 }
@@ -0,0 +1,466 @@
+/// Vault prices are quote per base in whole tokens, scaled by this factor.
+pub const PRICE_SCALE: u64 = 1_000_000;
+pub const PRICE_DECIMALS: u32 = 6;
+
+pub const MIN_TICK: i32 = -443_636;
+pub const MAX_TICK: i32 = 443_636;
+
+const Q64: u128 = 1 << 64;
+const MASK_64: u128 = u64::MAX as u128;
+
+/// 2^64 / sqrt(1.0001)^(2^i), used to build sqrt prices bit by bit.
+const SQRT_RATIO_X64: [u128; 19] = [
+    18445821805675392311,
+    18444899583751176498,
+    18443055278223354162,
+    18439367220385604838,
+    18431993317065449817,
+    18417254355718160513,
+    18387811781193591352,
+    18329067761203520168,
+    18212142134806087854,
+    17980523815641551639,
+    17526086738831147013,
+    16651378430235024244,
+    15030750278693429944,
+    12247334978882834399,
+    8131365268884726200,
+    3584323654723342297,
+    696457651847595233,
+    26294789957452057,
+    37481735321082,
+];
+
+/// 1 / log2(1.0001) as Q32.32.
+const INV_LOG2_TICK_X32: i128 = 29_771_933_215_624;
+
+/// a * b as a 256-bit (hi, lo) pair.
+fn full_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a1, a0) = (a >> 64, a & MASK_64);
+    let (b1, b0) = (b >> 64, b & MASK_64);
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let p11 = a1 * b1;
+    let mid = (p00 >> 64) + (p01 & MASK_64) + (p10 & MASK_64);
+    let lo = (p00 & MASK_64) | (mid << 64);
+    let hi = p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64);
+    (hi, lo)
+}
+
+/// floor(a * b / denom) without intermediate overflow; `None` if the result
+/// does not fit in a u128 or `denom` is zero.
+pub fn mul_div(a: u128, b: u128, denom: u128) -> Option<u128> {
+    if denom == 0 {
+        return None;
+    }
+    let (hi, lo) = full_mul(a, b);
+    if hi == 0 {
+        return Some(lo / denom);
+    }
+    if hi >= denom {
+        return None;
+    }
+    let mut rem = hi;
+    let mut quot = 0u128;
+    for i in (0..128).rev() {
+        let carry = rem >> 127;
+        rem = (rem << 1) | ((lo >> i) & 1);
+        if carry == 1 || rem >= denom {
+            rem = rem.wrapping_sub(denom);
+            quot |= 1 << i;
+        }
+    }
+    Some(quot)
+}
+
+/// floor(sqrt(x)).
+pub fn isqrt(x: u128) -> u128 {
+    if x < 2 {
+        return x;
+    }
+    let mut r = 1u128 << (128 - x.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (r + x / r) / 2;
+        if next >= r {
+            return r;
+        }
+        r = next;
+    }
+}
+
+/// ceil(a * b / denom); `None` on overflow or a zero `denom`.
+pub fn mul_div_ceil(a: u128, b: u128, denom: u128) -> Option<u128> {
+    let q = mul_div(a, b, denom)?;
+    if full_mul(q, denom) == full_mul(a, b) {
+        Some(q)
+    } else {
+        q.checked_add(1)
+    }
+}
+
+/// sqrt(1.0001^tick) as Q64.64.
+pub fn sqrt_price_at_tick(tick: i32) -> u128 {
+    let abs = tick.unsigned_abs().min(MAX_TICK as u32);
+    let mut ratio = Q64;
+    for (i, c) in SQRT_RATIO_X64.iter().enumerate() {
+        if abs & (1 << i) != 0 {
+            ratio = (ratio * c) >> 64;
+        }
+    }
+    if tick > 0 {
+        u128::MAX / ratio
+    } else {
+        ratio
+    }
+}
+
+/// 1.0001^tick as Q64.64, `None` if it does not fit.
+pub fn price_at_tick_x64(tick: i32) -> Option<u128> {
+    let s = sqrt_price_at_tick(tick);
+    mul_div(s, s, Q64)
+}
+
+/// log2(x / 2^64) as Q32.32.
+fn log2_x32(x: u128) -> i64 {
+    let msb = 127 - x.leading_zeros() as i64;
+    let mut log = (msb - 64) << 32;
+    let mut r = if msb >= 63 { x >> (msb - 63) } else { x << (63 - msb) };
+    for bit in (0..32).rev() {
+        r = (r * r) >> 63;
+        if r >= Q64 {
+            r >>= 1;
+            log |= 1 << bit;
+        }
+    }
+    log
+}
+
+/// Largest tick whose price is at or below `price_x64` (Q64.64).
+pub fn tick_at_price_x64(price_x64: u128) -> i32 {
+    if price_x64 == 0 {
+        return MIN_TICK;
+    }
+    let log = log2_x32(price_x64) as i128;
+    let mut tick = ((log * INV_LOG2_TICK_X32) >> 64).clamp(MIN_TICK as i128, MAX_TICK as i128) as i32;
+    // the log is approximate; settle the boundary against the exact tick prices
+    if tick < MAX_TICK && price_at_tick_x64(tick + 1).is_some_and(|p| p <= price_x64) {
+        tick += 1;
+    } else if tick > MIN_TICK && price_at_tick_x64(tick).is_none_or(|p| p > price_x64) {
+        tick -= 1;
+    }
+    tick
+}
+
+/// Square root of a Q64.64 price, as Q64.64 like a pool's `sqrt_price`.
+pub fn sqrt_price_x64(price_x64: u128) -> u128 {
+    if price_x64 >> 64 == 0 {
+        isqrt(price_x64 << 64)
+    } else {
+        isqrt(price_x64) << 32
+    }
+}
+
+/// Converts a vault price into a raw quote-atoms-per-base-atom Q64.64 price.
+pub fn price_to_x64(price: u64, base_decimals: u8, quote_decimals: u8) -> Option<u128> {
+    let num = 10u128.checked_pow(quote_decimals as u32)?;
+    let den = 10u128
+        .checked_pow(base_decimals as u32)?
+        .checked_mul(PRICE_SCALE as u128)?;
+    mul_div((price as u128) << 64, num, den)
+}
+
+/// Value of `base_amount` base atoms in quote atoms at a vault price.
+pub fn base_value_in_quote(base_amount: u64, price: u64, base_decimals: u8, quote_decimals: u8) -> Option<u64> {
+    let num = 10u128.checked_pow(quote_decimals as u32)?;
+    let den = 10u128
+        .checked_pow(base_decimals as u32)?
+        .checked_mul(PRICE_SCALE as u128)?;
+    u64::try_from(mul_div(base_amount as u128 * price as u128, num, den)?).ok()
+}
+
+/// Base atoms worth `quote_amount` quote atoms at a vault price, rounded down.
+pub fn quote_value_in_base(quote_amount: u64, price: u64, base_decimals: u8, quote_decimals: u8) -> Option<u64> {
+    let num = 10u128
+        .checked_pow(base_decimals as u32)?
+        .checked_mul(PRICE_SCALE as u128)?;
+    let den = 10u128.checked_pow(quote_decimals as u32)?.checked_mul(price as u128)?;
+    u64::try_from(mul_div(quote_amount as u128, num, den)?).ok()
+}
+
+/// Tick of a vault price, with base as the pool's token A.
+pub fn tick_at_price(price: u64, base_decimals: u8, quote_decimals: u8) -> Option<i32> {
+    price_to_x64(price, base_decimals, quote_decimals).map(tick_at_price_x64)
+}
+
+/// Band of a vault price. Bands are `band_size_bp` ticks of the price
+/// itself wide, so each spans the same relative move at any price level.
+/// `None` for a zero price or band size.
+pub fn band_of_price(price: u64, band_size_bp: u16) -> Option<i64> {
+    if price == 0 || band_size_bp == 0 {
+        return None;
+    }
+    let tick = tick_at_price(price, 0, 0)?;
+    Some((tick as i64).div_euclid(band_size_bp as i64))
+}
+
+/// Smallest vault price whose tick is at least `tick`.
+fn min_price_at_tick(tick: i64) -> Option<u64> {
+    if !(MIN_TICK as i64..=MAX_TICK as i64).contains(&tick) {
+        return None;
+    }
+    let p = price_at_tick_x64(tick as i32)?;
+    u64::try_from(mul_div_ceil(p, PRICE_SCALE as u128, Q64)?).ok()
+}
+
+/// Price bounds `[lower, upper)` of a band: exactly the prices for which
+/// `band_of_price` returns `band`.
+pub fn band_price_bounds(band: i64, band_size_bp: u16) -> Option<(u64, u64)> {
+    if band_size_bp == 0 {
+        return None;
+    }
+    let width = band_size_bp as i64;
+    let lower = min_price_at_tick(band.checked_mul(width)?)?;
+    let upper = min_price_at_tick(band.checked_add(1)?.checked_mul(width)?)?;
+    Some((lower, upper))
+}
+
+/// Widens `[lower, upper]` to multiples of `spacing` inside the usable range.
+pub fn align_tick_range(lower: i32, upper: i32, spacing: u16) -> (i32, i32) {
+    let sp = spacing.max(1) as i32;
+    let min = MIN_TICK.div_euclid(sp) * sp + if MIN_TICK.rem_euclid(sp) == 0 { 0 } else { sp };
+    let max = MAX_TICK.div_euclid(sp) * sp;
+    let lo = (lower.div_euclid(sp) * sp).clamp(min, max - sp);
+    let hi = (upper.div_euclid(sp) * sp + if upper.rem_euclid(sp) == 0 { 0 } else { sp }).clamp(lo + sp, max);
+    (lo, hi)
+}
+
+/// Liquidity backed by `amount_a` of token A between two sqrt prices.
+pub fn liquidity_for_a(sqrt_lower: u128, sqrt_upper: u128, amount_a: u64) -> Option<u128> {
+    let prod = mul_div(sqrt_lower, sqrt_upper, Q64)?;
+    mul_div(amount_a as u128, prod, sqrt_upper.checked_sub(sqrt_lower)?)
+}
+
+/// Liquidity backed by `amount_b` of token B between two sqrt prices.
+pub fn liquidity_for_b(sqrt_lower: u128, sqrt_upper: u128, amount_b: u64) -> Option<u128> {
+    mul_div(amount_b as u128, Q64, sqrt_upper.checked_sub(sqrt_lower)?)
+}
+
+/// Largest liquidity the two amounts can fund for a position at the current
+/// sqrt price.
+pub fn liquidity_for_amounts(
+    sqrt_price: u128,
+    sqrt_lower: u128,
+    sqrt_upper: u128,
+    amount_a: u64,
+    amount_b: u64,
+) -> Option<u128> {
+    if sqrt_price <= sqrt_lower {
+        liquidity_for_a(sqrt_lower, sqrt_upper, amount_a)
+    } else if sqrt_price < sqrt_upper {
+        Some(
+            liquidity_for_a(sqrt_price, sqrt_upper, amount_a)?
+                .min(liquidity_for_b(sqrt_lower, sqrt_price, amount_b)?),
+        )
+    } else {
+        liquidity_for_b(sqrt_lower, sqrt_upper, amount_b)
+    }
+}
+
+/// Token A backing `liquidity` between two sqrt prices, rounded down.
+pub fn amount_a_for_liquidity(sqrt_lower: u128, sqrt_upper: u128, liquidity: u128) -> Option<u64> {
+    let prod = mul_div(sqrt_lower, sqrt_upper, Q64)?;
+    u64::try_from(mul_div(liquidity, sqrt_upper.checked_sub(sqrt_lower)?, prod)?).ok()
+}
+
+/// Token B backing `liquidity` between two sqrt prices, rounded down.
+pub fn amount_b_for_liquidity(sqrt_lower: u128, sqrt_upper: u128, liquidity: u128) -> Option<u64> {
+    u64::try_from(mul_div(liquidity, sqrt_upper.checked_sub(sqrt_lower)?, Q64)?).ok()
+}
+
+/// Token amounts a position of `liquidity` holds at the current sqrt price.
+pub fn amounts_for_liquidity(
+    sqrt_price: u128,
+    sqrt_lower: u128,
+    sqrt_upper: u128,
+    liquidity: u128,
+) -> Option<(u64, u64)> {
+    if sqrt_price <= sqrt_lower {
+        Some((amount_a_for_liquidity(sqrt_lower, sqrt_upper, liquidity)?, 0))
+    } else if sqrt_price < sqrt_upper {
+        Some((
+            amount_a_for_liquidity(sqrt_price, sqrt_upper, liquidity)?,
+            amount_b_for_liquidity(sqrt_lower, sqrt_price, liquidity)?,
+        ))
+    } else {
+        Some((0, amount_b_for_liquidity(sqrt_lower, sqrt_upper, liquidity)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_matches_wide_arithmetic() {
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(mul_div(u128::MAX, 2, 4), Some(u128::MAX / 2));
+        assert_eq!(mul_div(1 << 100, 1 << 100, 1 << 90), Some(1 << 110));
+        assert_eq!(mul_div(u128::MAX, 2, 1), None);
+        assert_eq!(mul_div(1, 1, 0), None);
+    }
+
+    #[test]
+    fn isqrt_floors() {
+        for x in [0u128, 1, 2, 3, 4, 15, 16, 17, 1 << 64, u64::MAX as u128, u128::MAX] {
+            let r = isqrt(x);
+            assert!(r * r <= x && (r + 1).checked_mul(r + 1).is_none_or(|s| s > x));
+        }
+    }
+
+    #[test]
+    fn sqrt_price_matches_whirlpool_bounds() {
+        assert_eq!(sqrt_price_at_tick(0), Q64);
+        // MIN_SQRT_PRICE_X64 / MAX_SQRT_PRICE_X64 from the Whirlpool program
+        let min = sqrt_price_at_tick(MIN_TICK);
+        let max = sqrt_price_at_tick(MAX_TICK);
+        let whirlpool_max: u128 = 79_226_673_515_401_279_992_447_579_055;
+        assert!(min.abs_diff(4_295_048_016) <= 1);
+        assert!(max.abs_diff(whirlpool_max) < whirlpool_max / 1_000_000_000);
+    }
+
+    #[test]
+    fn tick_round_trips_through_price() {
+        for tick in [-200_000, -100_000, -1, 0, 1, 2, 887, 50_000, 300_000] {
+            let p = price_at_tick_x64(tick).unwrap();
+            assert_eq!(tick_at_price_x64(p), tick);
+            assert_eq!(tick_at_price_x64(p - 1), tick - 1);
+        }
+    }
+
+    #[test]
+    fn vault_price_maps_to_pool_tick() {
+        // 1.0 quote per base with equal decimals is tick 0
+        assert_eq!(tick_at_price(PRICE_SCALE, 6, 6), Some(0));
+        // $150 SOL (9 decimals) vs USDC (6 decimals) is ~0.15 atoms per atom
+        let tick = tick_at_price(150 * PRICE_SCALE, 9, 6).unwrap();
+        assert_eq!(tick, -18_973);
+    }
+
+    #[test]
+    fn mul_div_ceil_rounds_up_remainders() {
+        assert_eq!(mul_div_ceil(10, 10, 5), Some(20));
+        assert_eq!(mul_div_ceil(10, 10, 3), Some(34));
+        assert_eq!(mul_div_ceil(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(mul_div_ceil(u128::MAX, 3, 2), None);
+    }
+
+    fn assert_band_bounds_exact(band: i64, bp: u16) {
+        let (lo, hi) = band_price_bounds(band, bp).unwrap();
+        if lo > 0 {
+            assert_eq!(band_of_price(lo, bp), Some(band), "lower of {band}/{bp}");
+        }
+        if lo > 1 {
+            assert!(band_of_price(lo - 1, bp).unwrap() < band, "below {band}/{bp}");
+        }
+        if hi > lo {
+            assert_eq!(band_of_price(hi - 1, bp), Some(band), "upper of {band}/{bp}");
+        }
+        assert!(band_of_price(hi, bp).unwrap() > band, "above {band}/{bp}");
+    }
+
+    #[test]
+    fn bands_are_relative_width() {
+        // a 5 bp band is ~5 bp wide at $1 and at $50,000 alike
+        for price in [PRICE_SCALE, 50_000 * PRICE_SCALE] {
+            let (lo, hi) = band_price_bounds(band_of_price(price, 5).unwrap(), 5).unwrap();
+            let width_bps = (hi - lo) as u128 * 10_000 / lo as u128;
+            assert!((4..=5).contains(&width_bps), "{price}: {width_bps}");
+        }
+        assert_eq!(band_of_price(PRICE_SCALE, 5), Some(0));
+        assert_eq!(band_of_price(PRICE_SCALE - 1, 5), Some(-1));
+        assert_eq!(band_price_bounds(0, 5).unwrap().0, PRICE_SCALE);
+    }
+
+    #[test]
+    fn band_bounds_round_trip_on_edge_prices() {
+        let prices = [
+            1,
+            2,
+            999,
+            PRICE_SCALE - 1,
+            PRICE_SCALE,
+            PRICE_SCALE + 1,
+            150 * PRICE_SCALE,
+            123_456_789_012,
+            u64::MAX / 1_000_000,
+            u64::MAX / 2,
+        ];
+        for bp in [1u16, 5, 37, 100, 10_000, u16::MAX] {
+            for &price in &prices {
+                let band = band_of_price(price, bp).unwrap();
+                match band_price_bounds(band, bp) {
+                    Some((lo, hi)) => {
+                        assert!(lo <= price && price < hi, "{price} in band {band}/{bp}: [{lo}, {hi})");
+                        assert_band_bounds_exact(band, bp);
+                    }
+                    // only the top band's upper bound can fall outside u64
+                    None => assert_eq!(band_of_price(u64::MAX, bp), Some(band)),
+                }
+            }
+        }
+        for bp in [1u16, 3, 64] {
+            for band in -50..50 {
+                assert_band_bounds_exact(band, bp);
+            }
+        }
+    }
+
+    #[test]
+    fn band_math_rejects_bad_inputs() {
+        assert_eq!(band_of_price(0, 5), None);
+        assert_eq!(band_of_price(PRICE_SCALE, 0), None);
+        assert_eq!(band_price_bounds(0, 0), None);
+        assert_eq!(band_price_bounds(i64::MAX, 5), None);
+        assert_eq!(band_price_bounds(i64::MIN, 5), None);
+        // the top band's upper bound does not fit in a u64 price
+        let top = band_of_price(u64::MAX, 100).unwrap();
+        assert_eq!(band_price_bounds(top, 100), None);
+    }
+
+    #[test]
+    fn tick_range_is_aligned_and_non_empty() {
+        assert_eq!(align_tick_range(-5, 5, 64), (-64, 64));
+        assert_eq!(align_tick_range(64, 64, 64), (64, 128));
+        let (lo, hi) = align_tick_range(MIN_TICK, MAX_TICK, 64);
+        assert!(lo >= MIN_TICK && hi <= MAX_TICK && lo % 64 == 0 && hi % 64 == 0);
+    }
+
+    #[test]
+    fn liquidity_uses_the_limiting_side() {
+        let lo = sqrt_price_at_tick(-100);
+        let hi = sqrt_price_at_tick(100);
+        let mid = sqrt_price_at_tick(0);
+        let only_a = liquidity_for_amounts(lo, lo, hi, 1_000_000, 0).unwrap();
+        let only_b = liquidity_for_amounts(hi, lo, hi, 0, 1_000_000).unwrap();
+        assert!(only_a > 0 && only_b > 0);
+        let both = liquidity_for_amounts(mid, lo, hi, 1_000_000, 1).unwrap();
+        assert_eq!(both, liquidity_for_b(lo, mid, 1).unwrap());
+    }
+
+    #[test]
+    fn amounts_round_trip_through_liquidity() {
+        let lo = sqrt_price_at_tick(-100);
+        let hi = sqrt_price_at_tick(100);
+        let mid = sqrt_price_at_tick(0);
+        let l = liquidity_for_amounts(mid, lo, hi, 1_000_000, 1_000_000).unwrap();
+        let (a, b) = amounts_for_liquidity(mid, lo, hi, l).unwrap();
+        assert!(a <= 1_000_000 && b <= 1_000_000);
+        assert!(a >= 999_990 || b >= 999_990);
+        // out of range the position is all one token
+        assert_eq!(amounts_for_liquidity(lo, lo, hi, l).unwrap().1, 0);
+        assert_eq!(amounts_for_liquidity(hi, lo, hi, l).unwrap().0, 0);
+        let only_a = liquidity_for_a(lo, hi, 1_000_000).unwrap();
+        assert!((999_990..=1_000_000).contains(&amount_a_for_liquidity(lo, hi, only_a).unwrap()));
+    }
+}
@@ -0,0 +1,237 @@
+use anchor_lang::prelude::*;
+
+use crate::{math, VaultError};
+
+/// Shares and value added to both sides of the share price. A donation to a
+/// nearly empty vault then mostly accrues to shares nobody owns, so it
+/// cannot round later deposits down to nothing.
+pub const VIRTUAL_SHARES: u64 = 1_000;
+pub const VIRTUAL_VALUE: u64 = 1_000;
+
+/// Smallest deposit into a vault with no shares out, in quote atoms of value.
+pub const MIN_INITIAL_DEPOSIT_VALUE: u64 = 1_000_000;
+
+/// Everything the vault owns on behalf of share holders: idle balances net
+/// of fees owed to the treasury, plus what is deployed on the venue
+/// including fees earned there but not yet collected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Nav {
+    pub base:  u64,
+    pub quote: u64,
+}
+
+/// NAV as reported by `get_nav`. `nav_per_share` is quote atoms per share
+/// atom, scaled by `PRICE_SCALE`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NavSnapshot {
+    pub price:         u64,
+    pub base:          u64,
+    pub quote:         u64,
+    pub total_value:   u64,
+    pub share_supply:  u64,
+    pub nav_per_share: u64,
+}
+
+impl Nav {
+    pub fn new(idle: (u64, u64), accrued_fees: (u64, u64), deployed: (u64, u64)) -> Option<Self> {
+        Some(Self {
+            base:  idle.0.saturating_sub(accrued_fees.0).checked_add(deployed.0)?,
+            quote: idle.1.saturating_sub(accrued_fees.1).checked_add(deployed.1)?,
+        })
+    }
+
+    /// Total value in quote atoms at a vault price.
+    pub fn value(&self, price: u64, base_decimals: u8, quote_decimals: u8) -> Option<u64> {
+        math::base_value_in_quote(self.base, price, base_decimals, quote_decimals)?.checked_add(self.quote)
+    }
+
+    /// The largest part of `base_amount` / `quote_amount` in the same
+    /// proportion as the holdings. An empty vault takes both in full, a
+    /// one-sided vault only the side it holds.
+    pub fn ratio_matched(&self, base_amount: u64, quote_amount: u64) -> Option<(u64, u64)> {
+        match (self.base, self.quote) {
+            (0, 0) => return Some((base_amount, quote_amount)),
+            (_, 0) => return Some((base_amount, 0)),
+            (0, _) => return Some((0, quote_amount)),
+            _ => {}
+        }
+        let share = |amount: u64, num: u64, den: u64| {
+            math::mul_div(amount as u128, num as u128, den as u128).and_then(|v| u64::try_from(v).ok())
+        };
+        if base_amount as u128 * self.quote as u128 >= quote_amount as u128 * self.base as u128 {
+            Some((share(quote_amount, self.base, self.quote)?, quote_amount))
+        } else {
+            Some((base_amount, share(base_amount, self.quote, self.base)?))
+        }
+    }
+
+    /// Share of each holding `shares` out of `supply` is entitled to.
+    pub fn claim(&self, shares: u64, supply: u64) -> Option<(u64, u64)> {
+        let total = supply as u128 + VIRTUAL_SHARES as u128;
+        Some((
+            u64::try_from(math::mul_div(self.base as u128, shares as u128, total)?).ok()?,
+            u64::try_from(math::mul_div(self.quote as u128, shares as u128, total)?).ok()?,
+        ))
+    }
+}
+
+/// Rejects a deposit below `min_amount` on a side the vault holds (both
+/// sides of an empty vault); a side it does not hold is refunded anyway.
+pub fn check_min_deposit(nav: &Nav, base_amount: u64, quote_amount: u64, min_amount: u64) -> Result<()> {
+    let empty = nav.base == 0 && nav.quote == 0;
+    require!(
+        ((nav.base == 0 && !empty) || base_amount >= min_amount)
+            && ((nav.quote == 0 && !empty) || quote_amount >= min_amount),
+        VaultError::BelowMinDeposit
+    );
+    Ok(())
+}
+
+/// Rejects a first deposit below `MIN_INITIAL_DEPOSIT_VALUE`.
+pub fn check_deposit_value(deposit_value: u64, supply: u64) -> Result<()> {
+    require!(
+        supply > 0 || deposit_value >= MIN_INITIAL_DEPOSIT_VALUE,
+        VaultError::BelowMinInitialDeposit
+    );
+    Ok(())
+}
+
+/// Shares minted for a deposit worth `deposit_value` into a vault worth
+/// `nav_value` with `supply` shares out, rounded down. An empty vault mints
+/// one share atom per quote atom.
+pub fn shares_for_deposit(deposit_value: u64, nav_value: u64, supply: u64) -> Option<u64> {
+    let shares = math::mul_div(
+        deposit_value as u128,
+        supply as u128 + VIRTUAL_SHARES as u128,
+        nav_value as u128 + VIRTUAL_VALUE as u128,
+    )?;
+    u64::try_from(shares).ok()
+}
+
+/// Shares `deposit` and `deposit_single` mint: fails below the first deposit
+/// floor and when the deposit would mint nothing.
+pub fn deposit_shares(deposit_value: u64, nav_value: u64, supply: u64) -> Result<u64> {
+    check_deposit_value(deposit_value, supply)?;
+    let shares = shares_for_deposit(deposit_value, nav_value, supply).ok_or(VaultError::MathOverflow)?;
+    require!(shares > 0, VaultError::BelowMinDeposit);
+    Ok(shares)
+}
+
+/// Value per share atom scaled by `PRICE_SCALE`.
+pub fn nav_per_share(nav_value: u64, supply: u64) -> u64 {
+    math::mul_div(
+        nav_value as u128 + VIRTUAL_VALUE as u128,
+        math::PRICE_SCALE as u128,
+        supply as u128 + VIRTUAL_SHARES as u128,
+    )
+    .and_then(|v| u64::try_from(v).ok())
+    .unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 base = 2 quote, equal decimals
+    const PRICE: u64 = 2 * math::PRICE_SCALE;
+
+    #[test]
+    fn counts_deployed_liquidity_and_fees() {
+        let nav = Nav::new((100, 50), (10, 5), (400, 900)).unwrap();
+        assert_eq!(nav, Nav { base: 490, quote: 945 });
+        assert_eq!(nav.value(PRICE, 6, 6), Some(1_925));
+        // fees above the idle balance never make holdings negative
+        assert_eq!(Nav::new((0, 0), (10, 10), (5, 5)), Some(Nav { base: 5, quote: 5 }));
+    }
+
+    #[test]
+    fn deposits_are_priced_off_total_value() {
+        assert_eq!(shares_for_deposit(1_000_000, 0, 0), Some(1_000_000));
+        // half the vault is deployed: idle-only pricing would mint twice as many
+        let nav = Nav::new((250_000_000, 500_000_000), (0, 0), (250_000_000, 500_000_000)).unwrap();
+        let value = nav.value(PRICE, 6, 6).unwrap();
+        assert_eq!(shares_for_deposit(200_000_000, value, 2_000_000_000), Some(200_000_000));
+    }
+
+    #[test]
+    fn deposits_match_the_vault_mix() {
+        let nav = Nav { base: 1_000, quote: 2_000 };
+        assert_eq!(nav.ratio_matched(100, 500), Some((100, 200)));
+        assert_eq!(nav.ratio_matched(500, 100), Some((50, 100)));
+        assert_eq!(nav.ratio_matched(100, 200), Some((100, 200)));
+        // one-sided vaults take only that side
+        assert_eq!(Nav { base: 0, quote: 5 }.ratio_matched(100, 200), Some((0, 200)));
+        assert_eq!(Nav { base: 5, quote: 0 }.ratio_matched(100, 200), Some((100, 0)));
+        assert_eq!(Nav::default().ratio_matched(100, 7), Some((100, 7)));
+    }
+
+    #[test]
+    fn base_only_vault_takes_base_deposits() {
+        let nav = Nav { base: 5, quote: 0 };
+        assert_eq!(nav.ratio_matched(100, 0), Some((100, 0)));
+        assert_eq!(nav.ratio_matched(0, 0), Some((0, 0)));
+        // the minimum only applies to base
+        assert!(check_min_deposit(&nav, 100, 0, 50).is_ok());
+        assert_eq!(check_min_deposit(&nav, 10, 500, 50), err!(VaultError::BelowMinDeposit));
+    }
+
+    #[test]
+    fn quote_only_vault_takes_quote_deposits() {
+        let nav = Nav { base: 0, quote: 5 };
+        assert_eq!(nav.ratio_matched(0, 100), Some((0, 100)));
+        assert_eq!(nav.ratio_matched(300, 100), Some((0, 100)));
+        assert!(check_min_deposit(&nav, 0, 100, 50).is_ok());
+        assert_eq!(check_min_deposit(&nav, 500, 10, 50), err!(VaultError::BelowMinDeposit));
+        // an empty or two-sided vault needs both
+        assert!(check_min_deposit(&Nav::default(), 0, 100, 50).is_err());
+        assert!(check_min_deposit(&Nav { base: 1, quote: 1 }, 100, 10, 50).is_err());
+    }
+
+    #[test]
+    fn withdrawals_claim_every_holding() {
+        let nav = Nav::new((250_000_000, 500_000_000), (0, 0), (250_000_000, 500_000_000)).unwrap();
+        assert_eq!(nav.claim(500_000_000, 2_000_000_000), Some((124_999_937, 249_999_875)));
+        // the virtual shares' slice stays behind
+        assert_eq!(nav.claim(2_000_000_000, 2_000_000_000), Some((499_999_750, 999_999_500)));
+    }
+
+    #[test]
+    fn per_share_value() {
+        assert_eq!(nav_per_share(0, 0), math::PRICE_SCALE);
+        assert_eq!(nav_per_share(3_000_000_000, 2_000_000_000), 1_499_999);
+    }
+
+    #[test]
+    fn first_deposit_has_a_floor() {
+        assert_eq!(check_deposit_value(1, 0), err!(VaultError::BelowMinInitialDeposit));
+        assert!(check_deposit_value(MIN_INITIAL_DEPOSIT_VALUE, 0).is_ok());
+        assert!(check_deposit_value(1, 1_000_000).is_ok());
+    }
+
+    #[test]
+    fn donation_attack_no_longer_pays() {
+        // a one-atom first deposit is refused outright
+        assert_eq!(deposit_shares(1, 0, 0), err!(VaultError::BelowMinInitialDeposit));
+
+        // the attacker makes the smallest first deposit, redeems all but one
+        // share atom and donates straight to the vault's token accounts
+        let first = deposit_shares(MIN_INITIAL_DEPOSIT_VALUE, 0, 0).unwrap();
+        let (_, redeemed) = Nav { base: 0, quote: MIN_INITIAL_DEPOSIT_VALUE }.claim(first - 1, first).unwrap();
+        let attacker_shares: u64 = 1;
+        let donation = 1_000_000_000;
+        let nav_value = MIN_INITIAL_DEPOSIT_VALUE - redeemed + donation;
+
+        // a victim depositing just under the donation still gets shares
+        let victim = donation - 1;
+        let victim_shares = deposit_shares(victim, nav_value, attacker_shares).unwrap();
+        assert!(victim_shares > 0);
+
+        // and the virtual shares soak up the donation
+        let nav = Nav { base: 0, quote: nav_value + victim };
+        let supply = attacker_shares + victim_shares;
+        let (_, attacker_out) = nav.claim(attacker_shares, supply).unwrap();
+        let (_, victim_out) = nav.claim(victim_shares, supply).unwrap();
+        assert!(attacker_out < donation / 1_000);
+        assert!(victim_out >= victim - victim / 1_000);
+    }
+}
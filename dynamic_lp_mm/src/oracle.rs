@@ -0,0 +1,332 @@
+use anchor_lang::prelude::*;
+
+use crate::math::PRICE_DECIMALS;
+use crate::twap::read_twap_price;
+use crate::{OracleConfig, Vault, VaultError};
+
+pub const MAX_ORACLE_FEEDS: usize = 5;
+
+/// Kind of price source listed in an `OracleConfig`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OracleKind {
+    /// Pyth `PriceUpdateV2` account; `id` is the feed id.
+    Pyth,
+    /// Switchboard v2 aggregator; `id` is the aggregator address.
+    Switchboard,
+    /// The vault's own price history over `twap_window_sec`; `id` is unused.
+    Twap,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OracleFeed {
+    pub kind: OracleKind,
+    pub id:   [u8; 32],
+}
+
+/// Pyth pull oracle receiver program (rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ),
+/// owner of `PriceUpdateV2` accounts.
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    12, 183, 250, 187, 82, 247, 166, 72, 187, 91, 49, 125, 154, 1, 139, 144, 87, 203, 2, 71, 116, 250,
+    254, 1, 230, 196, 223, 152, 204, 56, 88, 129,
+]);
+
+const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+const VERIFICATION_FULL: u8 = 1;
+// discriminator + write_authority + verification_level (Full)
+const PRICE_MESSAGE_OFFSET: usize = 8 + 32 + 1;
+// feed_id + price + conf + exponent + publish_time
+const PRICE_MESSAGE_LEN: usize = 32 + 8 + 8 + 4 + 8;
+
+/// Price message of a fully verified Pyth `PriceUpdateV2` account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PythPrice {
+    pub feed_id:      [u8; 32],
+    pub price:        i64,
+    pub conf:         u64,
+    pub exponent:     i32,
+    pub publish_time: i64,
+}
+
+pub fn parse_price_update(data: &[u8]) -> Result<PythPrice> {
+    require!(
+        data.len() >= PRICE_MESSAGE_OFFSET + PRICE_MESSAGE_LEN
+            && data[..8] == PRICE_UPDATE_V2_DISCRIMINATOR,
+        VaultError::InvalidOracle
+    );
+    // partially verified updates carry fewer guardian signatures; reject them
+    require!(data[40] == VERIFICATION_FULL, VaultError::InvalidOracle);
+    let m = &data[PRICE_MESSAGE_OFFSET..];
+    Ok(PythPrice {
+        feed_id:      m[0..32].try_into().unwrap(),
+        price:        i64::from_le_bytes(m[32..40].try_into().unwrap()),
+        conf:         u64::from_le_bytes(m[40..48].try_into().unwrap()),
+        exponent:     i32::from_le_bytes(m[48..52].try_into().unwrap()),
+        publish_time: i64::from_le_bytes(m[52..60].try_into().unwrap()),
+    })
+}
+
+impl PythPrice {
+    /// Checks the feed, staleness and confidence, and converts the price to
+    /// the vault's price scale.
+    pub fn to_vault_price(&self, feed_id: &[u8; 32], now: i64, max_age_sec: u64, max_conf_bps: u16) -> Result<u64> {
+        require!(self.feed_id == *feed_id, VaultError::OracleFeedMismatch);
+        require!(self.price > 0, VaultError::InvalidOracle);
+        require!(
+            now.saturating_sub(self.publish_time) <= max_age_sec as i64,
+            VaultError::StaleOracle
+        );
+        require!(
+            (self.conf as u128) * 10_000 <= (self.price as u128) * (max_conf_bps as u128),
+            VaultError::OracleConfidenceTooWide
+        );
+        scale_price(self.price as u128, self.exponent)
+    }
+}
+
+/// `value * 10^expo` in vault price units.
+pub fn scale_price(value: u128, expo: i32) -> Result<u64> {
+    let shift = expo + PRICE_DECIMALS as i32;
+    let scaled = if shift >= 0 {
+        10u128.checked_pow(shift as u32).and_then(|m| value.checked_mul(m))
+    } else {
+        Some(10u128.checked_pow(shift.unsigned_abs()).map_or(0, |d| value / d))
+    };
+    let scaled = scaled
+        .and_then(|p| u64::try_from(p).ok())
+        .ok_or(VaultError::MathOverflow)?;
+    require!(scaled > 0, VaultError::InvalidOracle);
+    Ok(scaled)
+}
+
+/// Reads a Pyth feed from a `PriceUpdateV2` account.
+pub fn read_pyth_price(info: &AccountInfo, feed_id: &[u8; 32], vault: &Vault, now: i64) -> Result<u64> {
+    require_keys_eq!(*info.owner, PYTH_RECEIVER_PROGRAM_ID, VaultError::InvalidOracle);
+    parse_price_update(&info.try_borrow_data()?)?.to_vault_price(
+        feed_id,
+        now,
+        vault.oracle_max_age_sec,
+        vault.oracle_max_conf_bps,
+    )
+}
+
+/// Switchboard v2 program (SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f),
+/// owner of aggregator accounts.
+pub const SWITCHBOARD_V2_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
+    6, 136, 81, 198, 140, 104, 50, 240, 47, 165, 129, 177, 191, 73, 27, 119, 202, 65, 119, 107, 162, 185,
+    136, 181, 166, 250, 186, 142, 227, 162, 236, 144,
+]);
+
+const AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+// `AggregatorAccountData.latest_confirmed_round` field offsets (packed layout)
+const SB_NUM_SUCCESS: usize = 341;
+const SB_ROUND_OPEN_TIMESTAMP: usize = 358;
+const SB_RESULT: usize = 366;
+const SB_STD_DEVIATION: usize = 386;
+
+/// Latest confirmed round of a Switchboard v2 aggregator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwitchboardRound {
+    pub num_success:    u32,
+    pub open_timestamp: i64,
+    pub mantissa:       i128,
+    pub scale:          u32,
+    pub std_mantissa:   i128,
+    pub std_scale:      u32,
+}
+
+pub fn parse_aggregator(data: &[u8]) -> Result<SwitchboardRound> {
+    require!(
+        data.len() >= SB_STD_DEVIATION + 20 && data[..8] == AGGREGATOR_DISCRIMINATOR,
+        VaultError::InvalidOracle
+    );
+    let i128_at = |o: usize| i128::from_le_bytes(data[o..o + 16].try_into().unwrap());
+    let u32_at = |o: usize| u32::from_le_bytes(data[o..o + 4].try_into().unwrap());
+    Ok(SwitchboardRound {
+        num_success:    u32_at(SB_NUM_SUCCESS),
+        open_timestamp: i64::from_le_bytes(data[SB_ROUND_OPEN_TIMESTAMP..SB_ROUND_OPEN_TIMESTAMP + 8].try_into().unwrap()),
+        mantissa:       i128_at(SB_RESULT),
+        scale:          u32_at(SB_RESULT + 16),
+        std_mantissa:   i128_at(SB_STD_DEVIATION),
+        std_scale:      u32_at(SB_STD_DEVIATION + 16),
+    })
+}
+
+impl SwitchboardRound {
+    /// Same checks as Pyth, with the round's standard deviation as the
+    /// confidence interval.
+    pub fn to_vault_price(&self, now: i64, max_age_sec: u64, max_conf_bps: u16) -> Result<u64> {
+        require!(self.num_success > 0 && self.mantissa > 0, VaultError::InvalidOracle);
+        require!(
+            now.saturating_sub(self.open_timestamp) <= max_age_sec as i64,
+            VaultError::StaleOracle
+        );
+        let price = scale_price(self.mantissa as u128, -(self.scale as i32))?;
+        let std = if self.std_mantissa > 0 {
+            scale_price(self.std_mantissa as u128, -(self.std_scale as i32)).unwrap_or(0)
+        } else {
+            0
+        };
+        require!(
+            (std as u128) * 10_000 <= (price as u128) * (max_conf_bps as u128),
+            VaultError::OracleConfidenceTooWide
+        );
+        Ok(price)
+    }
+}
+
+pub fn read_switchboard_price(info: &AccountInfo, aggregator: &Pubkey, vault: &Vault, now: i64) -> Result<u64> {
+    require_keys_eq!(*info.key, *aggregator, VaultError::OracleFeedMismatch);
+    require_keys_eq!(*info.owner, SWITCHBOARD_V2_PROGRAM_ID, VaultError::InvalidOracle);
+    parse_aggregator(&info.try_borrow_data()?)?.to_vault_price(now, vault.oracle_max_age_sec, vault.oracle_max_conf_bps)
+}
+
+/// Reads every price source for the vault from the head of `accounts`:
+/// one account per feed in `config`, or a single Pyth account for the
+/// vault's own feed when no config is linked. `Twap` feeds are skipped
+/// without consuming an account unless `include_twap` is set. Returns the
+/// prices and the number of accounts consumed.
+pub fn read_prices(
+    vault: &Vault,
+    config: Option<&OracleConfig>,
+    accounts: &[AccountInfo],
+    now: i64,
+    include_twap: bool,
+) -> Result<(Vec<u64>, usize)> {
+    let Some(config) = config else {
+        let info = accounts.first().ok_or(VaultError::InvalidOracle)?;
+        return Ok((vec![read_pyth_price(info, &vault.oracle_feed_id, vault, now)?], 1));
+    };
+    let mut infos = accounts.iter();
+    let mut prices = Vec::with_capacity(config.feeds.len());
+    for feed in &config.feeds {
+        if feed.kind == OracleKind::Twap && !include_twap {
+            continue;
+        }
+        let info = infos.next().ok_or(VaultError::InvalidOracle)?;
+        prices.push(match feed.kind {
+            OracleKind::Pyth => read_pyth_price(info, &feed.id, vault, now)?,
+            OracleKind::Switchboard => read_switchboard_price(info, &Pubkey::new_from_array(feed.id), vault, now)?,
+            OracleKind::Twap => read_twap_price(info, vault, now)?,
+        });
+    }
+    require!(!prices.is_empty(), VaultError::InvalidOracle);
+    let used = accounts.len() - infos.len();
+    Ok((prices, used))
+}
+
+/// Median of the prices; the mean of the middle two for an even count.
+pub fn median(prices: &[u64]) -> u64 {
+    let mut sorted = prices.to_vec();
+    sorted.sort_unstable();
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        ((sorted[mid - 1] as u128 + sorted[mid] as u128) / 2) as u64
+    } else {
+        sorted[mid]
+    }
+}
+
+/// First source further than `max_bps` from `median`, with its divergence.
+pub fn find_divergence(prices: &[u64], median: u64, max_bps: u16) -> Option<(usize, u64)> {
+    prices.iter().enumerate().find_map(|(i, p)| {
+        let bps = (p.abs_diff(median) as u128 * 10_000 / median.max(1) as u128) as u64;
+        (bps > max_bps as u64).then_some((i, bps))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: [u8; 32] = [7; 32];
+
+    fn price_update(verification: &[u8], price: i64, conf: u64, exponent: i32, publish_time: i64) -> Vec<u8> {
+        let mut data = PRICE_UPDATE_V2_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&[1; 32]); // write_authority
+        data.extend_from_slice(verification);
+        data.extend_from_slice(&FEED);
+        data.extend_from_slice(&price.to_le_bytes());
+        data.extend_from_slice(&conf.to_le_bytes());
+        data.extend_from_slice(&exponent.to_le_bytes());
+        data.extend_from_slice(&publish_time.to_le_bytes());
+        data.extend_from_slice(&(publish_time - 1).to_le_bytes()); // prev_publish_time
+        data.extend_from_slice(&price.to_le_bytes()); // ema_price
+        data.extend_from_slice(&conf.to_le_bytes()); // ema_conf
+        data.extend_from_slice(&42u64.to_le_bytes()); // posted_slot
+        data
+    }
+
+    #[test]
+    fn parses_fully_verified_update() {
+        let data = price_update(&[VERIFICATION_FULL], 15_012_345_678, 1_000_000, -8, 1_700_000_000);
+        let p = parse_price_update(&data).unwrap();
+        assert_eq!(p.feed_id, FEED);
+        assert_eq!(p.price, 15_012_345_678);
+        assert_eq!(p.conf, 1_000_000);
+        assert_eq!(p.exponent, -8);
+        assert_eq!(p.publish_time, 1_700_000_000);
+        // $150.12345678 at 6 decimals
+        assert_eq!(p.to_vault_price(&FEED, 1_700_000_010, 30, 50), Ok(150_123_456));
+    }
+
+    #[test]
+    fn rejects_partial_verification_and_bad_discriminator() {
+        let partial = price_update(&[0, 5], 100, 1, -2, 0);
+        assert_eq!(parse_price_update(&partial), err!(VaultError::InvalidOracle));
+        let mut bad = price_update(&[VERIFICATION_FULL], 100, 1, -2, 0);
+        bad[0] ^= 1;
+        assert_eq!(parse_price_update(&bad), err!(VaultError::InvalidOracle));
+        assert_eq!(parse_price_update(&bad[..20]), err!(VaultError::InvalidOracle));
+    }
+
+    #[test]
+    fn enforces_feed_staleness_and_confidence() {
+        let p = parse_price_update(&price_update(&[VERIFICATION_FULL], 10_000, 10, -2, 1_000)).unwrap();
+        assert_eq!(p.to_vault_price(&[0; 32], 1_000, 60, 100), err!(VaultError::OracleFeedMismatch));
+        assert_eq!(p.to_vault_price(&FEED, 1_061, 60, 100), err!(VaultError::StaleOracle));
+        assert_eq!(p.to_vault_price(&FEED, 1_060, 60, 10), Ok(100_000_000));
+        // conf of 10 on 10_000 is 10 bps
+        assert_eq!(p.to_vault_price(&FEED, 1_000, 60, 9), err!(VaultError::OracleConfidenceTooWide));
+    }
+
+    #[test]
+    fn normalizes_exponents() {
+        assert_eq!(scale_price(123, 0), Ok(123_000_000));
+        assert_eq!(scale_price(123, 2), Ok(12_300_000_000));
+        assert_eq!(scale_price(123_456_789, -8), Ok(1_234_567));
+        assert_eq!(scale_price(1, -7), err!(VaultError::InvalidOracle));
+        assert_eq!(scale_price(u64::MAX as u128, 10), err!(VaultError::MathOverflow));
+    }
+
+    fn aggregator(num_success: u32, open_ts: i64, mantissa: i128, scale: u32, std: i128) -> Vec<u8> {
+        let mut data = vec![0u8; SB_STD_DEVIATION + 20];
+        data[..8].copy_from_slice(&AGGREGATOR_DISCRIMINATOR);
+        data[SB_NUM_SUCCESS..SB_NUM_SUCCESS + 4].copy_from_slice(&num_success.to_le_bytes());
+        data[SB_ROUND_OPEN_TIMESTAMP..SB_ROUND_OPEN_TIMESTAMP + 8].copy_from_slice(&open_ts.to_le_bytes());
+        data[SB_RESULT..SB_RESULT + 16].copy_from_slice(&mantissa.to_le_bytes());
+        data[SB_RESULT + 16..SB_RESULT + 20].copy_from_slice(&scale.to_le_bytes());
+        data[SB_STD_DEVIATION..SB_STD_DEVIATION + 16].copy_from_slice(&std.to_le_bytes());
+        data[SB_STD_DEVIATION + 16..SB_STD_DEVIATION + 20].copy_from_slice(&scale.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn parses_switchboard_round() {
+        let round = parse_aggregator(&aggregator(3, 500, 150_250_000_000, 9, 10_000_000)).unwrap();
+        assert_eq!(round.to_vault_price(510, 30, 10), Ok(150_250_000));
+        assert_eq!(round.to_vault_price(531, 30, 10), err!(VaultError::StaleOracle));
+        let wide = parse_aggregator(&aggregator(3, 500, 150_250_000_000, 9, 1_000_000_000)).unwrap();
+        assert_eq!(wide.to_vault_price(510, 30, 10), err!(VaultError::OracleConfidenceTooWide));
+        let empty = parse_aggregator(&aggregator(0, 500, 150_250_000_000, 9, 0)).unwrap();
+        assert_eq!(empty.to_vault_price(510, 30, 10), err!(VaultError::InvalidOracle));
+    }
+
+    #[test]
+    fn median_and_divergence() {
+        assert_eq!(median(&[5]), 5);
+        assert_eq!(median(&[9, 1, 5]), 5);
+        assert_eq!(median(&[1, 9, 3, 5]), 4);
+        assert_eq!(find_divergence(&[100, 101, 99], 100, 100), None);
+        assert_eq!(find_divergence(&[100, 103, 99], 100, 100), Some((1, 300)));
+    }
+}
@@ -0,0 +1,212 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+
+use crate::{math, Referral, VaultError};
+
+/// Scale of `Vault::referral_fee_index`.
+pub const REFERRAL_INDEX_SCALE: u128 = 1_000_000_000_000;
+
+impl Referral {
+    pub const SPACE: usize = 8 + 32 + 32 + 32 + 8 + 16 + 8 + 8 + 8 + 1;
+
+    pub fn has_referrer(&self) -> bool {
+        self.referrer != Pubkey::default()
+    }
+
+    /// Credits the performance fee shares escrowed for the user's tracked
+    /// shares since the last checkpoint.
+    pub fn settle(&mut self, fee_index: u128) -> Option<()> {
+        let earned = math::mul_div(self.shares as u128, fee_index.checked_sub(self.fee_index)?, REFERRAL_INDEX_SCALE)?;
+        self.owed_shares = self.owed_shares.checked_add(u64::try_from(earned).ok()?)?;
+        self.fee_index = fee_index;
+        Some(())
+    }
+
+    /// Records a deposit of `shares` at `fee_index`. Only the deposit that
+    /// created the PDA (`first`) may name a referrer, which is then fixed
+    /// for good. Returns the shares that count as referred.
+    pub fn record_deposit(&mut self, first: bool, referrer: Option<Pubkey>, shares: u64, fee_index: u128) -> Result<u64> {
+        if let Some(referrer) = referrer {
+            require!(first, VaultError::ReferrerAlreadyFixed);
+            require_keys_neq!(referrer, self.user, VaultError::InvalidReferrer);
+            self.referrer = referrer;
+        }
+        if !self.has_referrer() {
+            return Ok(0);
+        }
+        self.settle(fee_index).ok_or(VaultError::MathOverflow)?;
+        self.shares = self.shares.checked_add(shares).ok_or(VaultError::MathOverflow)?;
+        Ok(shares)
+    }
+}
+
+/// The referrers' part of a performance fee of `fee_shares`:
+/// `referral_share_bps` of what referred shares paid, and the matching
+/// increase of the fee index.
+///
+/// Referred shares are the shares each referral PDA recorded at deposit,
+/// less what its user burned, so `referred_shares` is always the sum of
+/// `Referral::shares` and the index pays out at most `part`. Share tokens
+/// can move between wallets without the program seeing it: recorded shares
+/// sent away keep earning, and once the recipient burns them
+/// `referred_shares` can exceed the shares referred users still hold, even
+/// the supply. It is capped at the supply, so at worst referrers get their
+/// cut of the whole fee.
+pub fn referral_fee_shares(
+    fee_shares: u64,
+    referred_shares: u64,
+    supply: u64,
+    referral_share_bps: u16,
+) -> Option<(u64, u128)> {
+    if referred_shares == 0 || supply == 0 {
+        return Some((0, 0));
+    }
+    let paid = math::mul_div(fee_shares as u128, referred_shares.min(supply) as u128, supply as u128)?;
+    let part = paid * referral_share_bps.min(10_000) as u128 / 10_000;
+    let index = math::mul_div(part, REFERRAL_INDEX_SCALE, referred_shares as u128)?;
+    Some((u64::try_from(part).ok()?, index))
+}
+
+/// Reads a user's referral PDA, `None` until their first deposit.
+pub fn load(info: &AccountInfo) -> Result<Option<Referral>> {
+    if info.data_is_empty() {
+        return Ok(None);
+    }
+    require_keys_eq!(*info.owner, crate::ID, VaultError::InvalidReferral);
+    Ok(Some(Referral::try_deserialize(&mut &info.try_borrow_data()?[..])?))
+}
+
+pub fn store(referral: &Referral, info: &AccountInfo) -> Result<()> {
+    referral.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])
+}
+
+/// Creates a user's referral PDA, paid for by the user. Lamports already
+/// sent to the address are topped up rather than rejected.
+pub fn create<'info>(
+    info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(Referral::SPACE);
+    if info.lamports() == 0 {
+        return system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::CreateAccount { from: payer.clone(), to: info.clone() },
+                &[seeds],
+            ),
+            rent,
+            Referral::SPACE as u64,
+            &crate::ID,
+        );
+    }
+    let top_up = rent.saturating_sub(info.lamports());
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer { from: payer.clone(), to: info.clone() },
+            ),
+            top_up,
+        )?;
+    }
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Allocate { account_to_allocate: info.clone() },
+            &[seeds],
+        ),
+        Referral::SPACE as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Assign { account_to_assign: info.clone() },
+            &[seeds],
+        ),
+        &crate::ID,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn referral(shares: u64) -> Referral {
+        Referral {
+            vault: Pubkey::default(),
+            user: Pubkey::default(),
+            referrer: Pubkey::new_unique(),
+            shares,
+            fee_index: 0,
+            owed_base: 0,
+            owed_quote: 0,
+            owed_shares: 0,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn referrers_get_their_share_of_referred_fees() {
+        // 40% of the supply is referred; referrers get 25% of what it paid
+        let (part, index) = referral_fee_shares(1_000_000, 400_000_000, 1_000_000_000, 2_500).unwrap();
+        assert_eq!(part, 100_000);
+        let (mut a, mut b) = (referral(300_000_000), referral(100_000_000));
+        a.settle(index).unwrap();
+        b.settle(index).unwrap();
+        assert_eq!((a.owed_shares, b.owed_shares), (75_000, 25_000));
+        // settling again credits nothing new
+        a.settle(index).unwrap();
+        assert_eq!(a.owed_shares, 75_000);
+    }
+
+    #[test]
+    fn referrer_is_fixed_at_the_first_deposit() {
+        let user = Pubkey::new_unique();
+        let unreferred = || Referral { user, referrer: Pubkey::default(), ..referral(0) };
+
+        let mut first = unreferred();
+        let referrer = Pubkey::new_unique();
+        assert_eq!(first.record_deposit(true, Some(referrer), 100, 0).unwrap(), 100);
+        assert_eq!((first.referrer, first.shares), (referrer, 100));
+        // later deposits keep counting, but cannot name anyone
+        assert_eq!(first.record_deposit(false, None, 50, 0).unwrap(), 50);
+        assert!(first.record_deposit(false, Some(referrer), 50, 0).is_err());
+        assert!(first.record_deposit(false, Some(Pubkey::new_unique()), 50, 0).is_err());
+        assert_eq!((first.referrer, first.shares), (referrer, 150));
+
+        // a first deposit without a referrer leaves the user unreferred for good
+        let mut none = unreferred();
+        assert_eq!(none.record_deposit(true, None, 100, 0).unwrap(), 0);
+        assert!(none.record_deposit(false, Some(referrer), 100, 0).is_err());
+        assert!(!none.has_referrer());
+        assert_eq!(none.shares, 0);
+
+        assert!(unreferred().record_deposit(true, Some(user), 100, 0).is_err());
+    }
+
+    #[test]
+    fn transferred_shares_are_capped_at_the_supply() {
+        // a referred user deposits 1_000 shares and sends them all to an
+        // unreferred wallet, which burns them; another 500 unreferred remain
+        let mut sender = referral(1_000);
+        let (part, index) = referral_fee_shares(1_000, 1_000, 500, 2_500).unwrap();
+        // the whole fee is counted as referred, never more
+        assert_eq!(part, 250);
+        sender.settle(index).unwrap();
+        assert_eq!(sender.owed_shares, 250);
+    }
+
+    #[test]
+    fn escrow_covers_every_claim() {
+        // more tracked than the supply (shares moved between wallets): claims still sum to the escrow
+        let (part, index) = referral_fee_shares(999_999, 1_500, 1_000, 10_000).unwrap();
+        assert_eq!(part, 999_999);
+        let (mut a, mut b) = (referral(1_000), referral(500));
+        a.settle(index).unwrap();
+        b.settle(index).unwrap();
+        assert!(a.owed_shares + b.owed_shares <= part);
+        assert_eq!(referral_fee_shares(1_000, 0, 1_000, 10_000), Some((0, 0)));
+    }
+}
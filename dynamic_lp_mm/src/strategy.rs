@@ -0,0 +1,250 @@
+use anchor_lang::prelude::*;
+
+use crate::math;
+use crate::VaultError;
+
+/// What makes the vault move its liquidity.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RebalanceMode {
+    /// Admin-driven; any band change is applied.
+    Manual,
+    /// Price has left the current band by more than `hysteresis_bps` past
+    /// its edge.
+    BandCross { hysteresis_bps: u16 },
+    /// At least `interval_sec` since the last rebalance.
+    TimeInterval { interval_sec: u64 },
+    /// Price moved at least `threshold_bps` since the last rebalance.
+    Volatility { threshold_bps: u16 },
+    /// Base share of idle value is more than `max_skew_bps` off
+    /// `Vault::target_base_bps`.
+    InventorySkew { max_skew_bps: u16 },
+}
+
+/// Vault state a trigger is evaluated against. `old_band` is on the
+/// `band_size_bp` grid of the last rebalance, `band` on `new_band_size_bp`.
+pub struct TriggerState {
+    pub band:                 i64,
+    pub old_band:             i64,
+    pub band_size_bp:         u16,
+    pub new_band_size_bp:     u16,
+    pub price:                u64,
+    pub last_rebalance_price: u64,
+    pub now:                  u64,
+    pub last_rebalance_ts:    u64,
+    pub base_value:           u64,
+    pub quote_amount:         u64,
+    pub target_base_bps:      u16,
+}
+
+impl RebalanceMode {
+    pub fn validate(&self) -> Result<()> {
+        let ok = match *self {
+            RebalanceMode::Manual => true,
+            RebalanceMode::BandCross { hysteresis_bps } => hysteresis_bps < 10_000,
+            RebalanceMode::TimeInterval { interval_sec } => interval_sec > 0,
+            RebalanceMode::Volatility { threshold_bps } => threshold_bps > 0 && threshold_bps <= 10_000,
+            RebalanceMode::InventorySkew { max_skew_bps } => max_skew_bps > 0 && max_skew_bps < 5_000,
+        };
+        require!(ok, VaultError::InvalidRebalanceMode);
+        Ok(())
+    }
+
+    /// Whether the vault should rebalance now. Automated modes fail with
+    /// their own error when the trigger has not fired; `Manual` simply
+    /// reports whether the band changed, which a new band width always does.
+    pub fn should_rebalance(&self, s: &TriggerState) -> Result<bool> {
+        if s.last_rebalance_ts == 0 {
+            return Ok(true);
+        }
+        match *self {
+            RebalanceMode::Manual => Ok(s.band != s.old_band || s.new_band_size_bp != s.band_size_bp),
+            RebalanceMode::BandCross { hysteresis_bps } => {
+                let (lo, hi) = math::band_price_bounds(s.old_band, s.band_size_bp).ok_or(VaultError::MathOverflow)?;
+                let h = hysteresis_bps as u128;
+                let below = (s.price as u128) * 10_000 < (lo as u128) * (10_000 - h);
+                let above = (s.price as u128) * 10_000 >= (hi as u128) * (10_000 + h);
+                require!(below || above, VaultError::BandNotCrossed);
+                Ok(true)
+            }
+            RebalanceMode::TimeInterval { interval_sec } => {
+                require!(
+                    s.now.saturating_sub(s.last_rebalance_ts) >= interval_sec,
+                    VaultError::IntervalNotElapsed
+                );
+                Ok(true)
+            }
+            RebalanceMode::Volatility { threshold_bps } => {
+                let moved = s.price.abs_diff(s.last_rebalance_price) as u128 * 10_000;
+                require!(
+                    moved >= (s.last_rebalance_price as u128) * (threshold_bps as u128),
+                    VaultError::VolatilityBelowThreshold
+                );
+                Ok(true)
+            }
+            RebalanceMode::InventorySkew { max_skew_bps } => {
+                require!(
+                    inventory_skew_bps(s.base_value, s.quote_amount, s.target_base_bps).unsigned_abs()
+                        > max_skew_bps as u64,
+                    VaultError::InventoryWithinBounds
+                );
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Band width for a volatility estimate: `vol_bps * vol_multiplier_bps /
+/// 10_000` clamped to `[min_bp, max_bp]`. Without an estimate the static
+/// width is clamped instead; with adaptive sizing off it is used as is.
+pub fn effective_band_bp(base_bp: u16, min_bp: u16, max_bp: u16, vol_multiplier_bps: u16, vol_bps: Option<u64>) -> u16 {
+    if max_bp == 0 {
+        return base_bp;
+    }
+    let target = match vol_bps {
+        Some(vol) => vol.saturating_mul(vol_multiplier_bps as u64) / 10_000,
+        None => base_bp as u64,
+    };
+    target.clamp(min_bp as u64, max_bp as u64) as u16
+}
+
+/// Base value as a share of total value, in bps.
+pub fn base_share_bps(base_value: u64, quote_amount: u64) -> u64 {
+    let total = base_value as u128 + quote_amount as u128;
+    if total == 0 {
+        return 5_000;
+    }
+    (base_value as u128 * 10_000 / total) as u64
+}
+
+/// How far the base share of value is above (positive) or below target.
+pub fn inventory_skew_bps(base_value: u64, quote_amount: u64, target_base_bps: u16) -> i64 {
+    base_share_bps(base_value, quote_amount) as i64 - target_base_bps as i64
+}
+
+/// Bands to move the ladder centre by to mean-revert inventory: one band
+/// per `max_skew_bps` of skew, against the excess side, at most `max_offset`.
+/// Excess base moves the centre down so asks sit closer to the price.
+pub fn skew_band_offset(skew_bps: i64, max_skew_bps: u16, max_offset: i64) -> i64 {
+    if max_skew_bps == 0 || skew_bps.unsigned_abs() <= max_skew_bps as u64 {
+        return 0;
+    }
+    (-skew_bps / max_skew_bps as i64).clamp(-max_offset, max_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> TriggerState {
+        TriggerState {
+            band: 20,
+            old_band: 20,
+            band_size_bp: 5,
+            new_band_size_bp: 5,
+            price: 1_010_300,
+            last_rebalance_price: 1_010_300,
+            now: 1_000,
+            last_rebalance_ts: 900,
+            base_value: 500,
+            quote_amount: 500,
+            target_base_bps: 5_000,
+        }
+    }
+
+    #[test]
+    fn manual_follows_band_changes() {
+        let mut s = state();
+        assert_eq!(RebalanceMode::Manual.should_rebalance(&s), Ok(false));
+        s.band = 21;
+        assert_eq!(RebalanceMode::Manual.should_rebalance(&s), Ok(true));
+        // the same index on a wider grid is a different range
+        s.band = 20;
+        s.new_band_size_bp = 10;
+        assert_eq!(RebalanceMode::Manual.should_rebalance(&s), Ok(true));
+    }
+
+    #[test]
+    fn band_cross_applies_hysteresis() {
+        let mode = RebalanceMode::BandCross { hysteresis_bps: 100 };
+        let mut s = state();
+        let (lo, hi) = math::band_price_bounds(20, 5).unwrap();
+        let above = (hi as u128 * 10_100).div_ceil(10_000) as u64;
+        let below = (lo as u128 * 9_900).div_ceil(10_000) as u64;
+        s.price = above - 1;
+        assert_eq!(mode.should_rebalance(&s), err!(VaultError::BandNotCrossed));
+        s.price = above;
+        assert_eq!(mode.should_rebalance(&s), Ok(true));
+        s.price = below;
+        assert_eq!(mode.should_rebalance(&s), err!(VaultError::BandNotCrossed));
+        s.price = below - 1;
+        assert_eq!(mode.should_rebalance(&s), Ok(true));
+    }
+
+    #[test]
+    fn time_interval_and_volatility() {
+        let s = state();
+        assert_eq!(
+            RebalanceMode::TimeInterval { interval_sec: 101 }.should_rebalance(&s),
+            err!(VaultError::IntervalNotElapsed)
+        );
+        assert_eq!(RebalanceMode::TimeInterval { interval_sec: 100 }.should_rebalance(&s), Ok(true));
+        let mut s = state();
+        s.price = 1_020_402;
+        assert_eq!(
+            RebalanceMode::Volatility { threshold_bps: 100 }.should_rebalance(&s),
+            err!(VaultError::VolatilityBelowThreshold)
+        );
+        s.price = 1_020_403;
+        assert_eq!(RebalanceMode::Volatility { threshold_bps: 100 }.should_rebalance(&s), Ok(true));
+    }
+
+    #[test]
+    fn inventory_skew() {
+        let mode = RebalanceMode::InventorySkew { max_skew_bps: 1_000 };
+        let mut s = state();
+        assert_eq!(mode.should_rebalance(&s), err!(VaultError::InventoryWithinBounds));
+        s.base_value = 700;
+        s.quote_amount = 300;
+        assert_eq!(mode.should_rebalance(&s), Ok(true));
+        // the same inventory is on target for a 70% base vault
+        s.target_base_bps = 7_000;
+        assert_eq!(mode.should_rebalance(&s), err!(VaultError::InventoryWithinBounds));
+    }
+
+    #[test]
+    fn skew_moves_center_against_excess() {
+        assert_eq!(inventory_skew_bps(700, 300, 5_000), 2_000);
+        assert_eq!(inventory_skew_bps(0, 0, 6_000), -1_000);
+        assert_eq!(skew_band_offset(2_000, 1_000, 4), -2);
+        assert_eq!(skew_band_offset(-2_500, 1_000, 4), 2);
+        assert_eq!(skew_band_offset(1_000, 1_000, 4), 0);
+        assert_eq!(skew_band_offset(9_000, 1_000, 3), -3);
+        assert_eq!(skew_band_offset(9_000, 0, 3), 0);
+    }
+
+    #[test]
+    fn first_rebalance_always_fires() {
+        let mut s = state();
+        s.last_rebalance_ts = 0;
+        assert_eq!(RebalanceMode::BandCross { hysteresis_bps: 0 }.should_rebalance(&s), Ok(true));
+        assert_eq!(RebalanceMode::Manual.should_rebalance(&s), Ok(true));
+    }
+
+    #[test]
+    fn adaptive_band_is_clamped() {
+        assert_eq!(effective_band_bp(5, 0, 0, 0, Some(400)), 5);
+        // 2x a 30 bp volatility
+        assert_eq!(effective_band_bp(5, 10, 100, 20_000, Some(30)), 60);
+        assert_eq!(effective_band_bp(5, 10, 100, 20_000, Some(1)), 10);
+        assert_eq!(effective_band_bp(5, 10, 100, 20_000, Some(u64::MAX)), 100);
+        assert_eq!(effective_band_bp(5, 10, 100, 20_000, None), 10);
+    }
+
+    #[test]
+    fn validates_parameters() {
+        assert!(RebalanceMode::TimeInterval { interval_sec: 0 }.validate().is_err());
+        assert!(RebalanceMode::Volatility { threshold_bps: 0 }.validate().is_err());
+        assert!(RebalanceMode::InventorySkew { max_skew_bps: 5_000 }.validate().is_err());
+        assert!(RebalanceMode::BandCross { hysteresis_bps: 50 }.validate().is_ok());
+    }
+}
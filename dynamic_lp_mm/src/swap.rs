@@ -0,0 +1,163 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token;
+
+use crate::strategy;
+use crate::venue::invoke_venue;
+use crate::{math, Vault, VaultError};
+
+/// A swap route for `Vault::swap_program`, built off-chain (e.g. a Jupiter
+/// route). The program and then `num_accounts - 1` route accounts are taken
+/// from `remaining_accounts` ahead of the venue accounts.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SwapLeg {
+    pub data:         Vec<u8>,
+    pub num_accounts: u8,
+}
+
+/// Token balance changes of a swap; negative is sold.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct SwapResult {
+    pub base_delta:  i64,
+    pub quote_delta: i64,
+}
+
+/// Runs `leg` against the vault's token accounts with the vault PDA signing,
+/// then checks the outcome with `check_swap`. Returns the balance changes and
+/// the accounts left over for the venue.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_to_ratio<'a, 'info>(
+    vault: &Vault,
+    vault_info: &AccountInfo<'info>,
+    base_vault: &AccountInfo<'info>,
+    quote_vault: &AccountInfo<'info>,
+    remaining: &'a [AccountInfo<'info>],
+    leg: &SwapLeg,
+    price: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<(SwapResult, &'a [AccountInfo<'info>])> {
+    require_keys_neq!(vault.swap_program, Pubkey::default(), VaultError::SwapDisabled);
+    let n = leg.num_accounts as usize;
+    require!(n >= 1 && remaining.len() >= n, VaultError::InvalidVenueAccounts);
+    let (route, rest) = remaining.split_at(n);
+    require_keys_eq!(*route[0].key, vault.swap_program, VaultError::InvalidVenueAccounts);
+
+    let before = (token::accessor::amount(base_vault)?, token::accessor::amount(quote_vault)?);
+    let metas: Vec<(&AccountInfo<'info>, bool)> = route[1..].iter().map(|a| (a, a.is_writable)).collect();
+    invoke_venue(&route[0], vault_info.key, signer_seeds, leg.data.clone(), &metas)?;
+    let after = (token::accessor::amount(base_vault)?, token::accessor::amount(quote_vault)?);
+
+    let result = SwapLimits::of(vault).check(before, after, price)?;
+    Ok((result, rest))
+}
+
+/// Vault settings a swap is checked against.
+pub struct SwapLimits {
+    pub base_decimals:     u8,
+    pub quote_decimals:    u8,
+    pub target_base_bps:   u16,
+    pub accrued_fee_base:  u64,
+    pub accrued_fee_quote: u64,
+    pub max_notional:      u64,
+    pub max_slippage_bps:  u16,
+}
+
+impl SwapLimits {
+    pub fn of(vault: &Vault) -> Self {
+        Self {
+            base_decimals:     vault.base_decimals,
+            quote_decimals:    vault.quote_decimals,
+            target_base_bps:   vault.target_base_bps,
+            accrued_fee_base:  vault.accrued_fee_base,
+            accrued_fee_quote: vault.accrued_fee_quote,
+            max_notional:      vault.max_swap_notional,
+            max_slippage_bps:  vault.max_swap_slippage_bps,
+        }
+    }
+
+    /// Accepts a swap only if it sold the side the vault holds too much of,
+    /// left inventory no further from target, kept fees owed to the
+    /// treasury, stayed under the notional cap, and received at least the
+    /// value of what it sold at `price` less the slippage allowance.
+    pub fn check(&self, before: (u64, u64), after: (u64, u64), price: u64) -> Result<SwapResult> {
+        let value = |base: u64| {
+            math::base_value_in_quote(base, price, self.base_decimals, self.quote_decimals)
+                .ok_or(VaultError::MathOverflow)
+        };
+        let skew_before = strategy::inventory_skew_bps(value(before.0)?, before.1, self.target_base_bps);
+        let skew_after = strategy::inventory_skew_bps(value(after.0)?, after.1, self.target_base_bps);
+
+        let (sold_value, bought_value, sells_base) = if after.0 < before.0 && after.1 > before.1 {
+            (value(before.0 - after.0)?, after.1 - before.1, true)
+        } else if after.1 < before.1 && after.0 > before.0 {
+            (before.1 - after.1, value(after.0 - before.0)?, false)
+        } else {
+            return err!(VaultError::InvalidSwap);
+        };
+        require!(
+            (skew_before > 0) == sells_base && skew_after.unsigned_abs() <= skew_before.unsigned_abs(),
+            VaultError::InvalidSwap
+        );
+        require!(
+            after.0 >= self.accrued_fee_base && after.1 >= self.accrued_fee_quote,
+            VaultError::InvalidSwap
+        );
+        require!(sold_value <= self.max_notional, VaultError::SwapNotionalExceeded);
+        require!(
+            bought_value as u128 * 10_000 >= sold_value as u128 * (10_000 - self.max_slippage_bps as u128),
+            VaultError::SwapSlippageExceeded
+        );
+
+        Ok(SwapResult {
+            base_delta:  after.0 as i64 - before.0 as i64,
+            quote_delta: after.1 as i64 - before.1 as i64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 base = 2 quote, equal decimals
+    const PRICE: u64 = 2 * math::PRICE_SCALE;
+
+    fn limits() -> SwapLimits {
+        SwapLimits {
+            base_decimals:     6,
+            quote_decimals:    6,
+            target_base_bps:   5_000,
+            accrued_fee_base:  0,
+            accrued_fee_quote: 0,
+            max_notional:      1_000,
+            max_slippage_bps:  100,
+        }
+    }
+
+    #[test]
+    fn sells_excess_base_within_bounds() {
+        // 1_000 base (2_000 quote of value) vs 0 quote; sell 250 base for 495 quote
+        let r = limits().check((1_000, 0), (750, 495), PRICE).unwrap();
+        assert_eq!(r, SwapResult { base_delta: -250, quote_delta: 495 });
+        assert_eq!(limits().check((1_000, 0), (750, 494), PRICE), err!(VaultError::SwapSlippageExceeded));
+    }
+
+    #[test]
+    fn rejects_swaps_away_from_target() {
+        // buying more base while already base-heavy
+        assert_eq!(limits().check((1_000, 100), (1_040, 20), PRICE), err!(VaultError::InvalidSwap));
+        // overshooting the target by more than the starting skew
+        let mut l = limits();
+        l.max_notional = u64::MAX;
+        assert_eq!(l.check((1_000, 900), (300, 2_300), PRICE), err!(VaultError::InvalidSwap));
+        // nothing sold
+        assert_eq!(limits().check((1_000, 0), (1_000, 10), PRICE), err!(VaultError::InvalidSwap));
+    }
+
+    #[test]
+    fn enforces_notional_cap_and_fees() {
+        assert_eq!(limits().check((2_000, 0), (1_400, 1_200), PRICE), err!(VaultError::SwapNotionalExceeded));
+        let mut l = limits();
+        l.accrued_fee_base = 800;
+        assert_eq!(l.check((1_000, 0), (750, 500), PRICE), err!(VaultError::InvalidSwap));
+    }
+}
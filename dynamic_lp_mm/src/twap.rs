@@ -0,0 +1,179 @@
+use anchor_lang::prelude::*;
+
+use crate::math;
+use crate::{PriceHistory, Vault, VaultError};
+
+pub const PRICE_HISTORY_LEN: usize = 64;
+
+/// Weight of the newest squared return in the volatility EWMA, in bps.
+pub const VOL_EWMA_ALPHA_BPS: u128 = 2_000;
+
+/// A recorded price; `cumulative` is the running sum of price * seconds up
+/// to `timestamp`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct PriceObservation {
+    pub timestamp:  i64,
+    pub price:      u64,
+    pub cumulative: u128,
+}
+
+impl PriceHistory {
+    pub fn newest(&self) -> Option<&PriceObservation> {
+        (self.len > 0).then(|| &self.observations[self.head as usize])
+    }
+
+    /// Appends an observation, overwriting the oldest once the ring is full.
+    pub fn record(&mut self, now: i64, price: u64) -> Result<()> {
+        let cumulative = match self.newest() {
+            Some(last) => {
+                require!(
+                    now >= last.timestamp.saturating_add(self.min_interval_sec.max(1) as i64),
+                    VaultError::PriceRecordedTooSoon
+                );
+                let elapsed = (now - last.timestamp) as u128;
+                last.cumulative
+                    .checked_add((last.price as u128).checked_mul(elapsed).ok_or(VaultError::MathOverflow)?)
+                    .ok_or(VaultError::MathOverflow)?
+            }
+            None => 0,
+        };
+        if self.len > 0 {
+            self.head = ((self.head as usize + 1) % PRICE_HISTORY_LEN) as u16;
+        }
+        self.observations[self.head as usize] = PriceObservation { timestamp: now, price, cumulative };
+        self.len = (self.len + 1).min(PRICE_HISTORY_LEN as u16);
+        Ok(())
+    }
+
+    /// Observations from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &PriceObservation> {
+        let start = self.head as usize + PRICE_HISTORY_LEN + 1 - self.len as usize;
+        (0..self.len as usize).map(move |i| &self.observations[(start + i) % PRICE_HISTORY_LEN])
+    }
+
+    /// Realized volatility per observation interval in bps: the square root
+    /// of an EWMA of squared log returns. One tick is a ~1 bp log return, so
+    /// returns are taken as tick differences. `None` without two observations.
+    pub fn ewma_volatility_bps(&self) -> Option<u64> {
+        let mut prev: Option<i32> = None;
+        let mut var: Option<u128> = None;
+        for o in self.iter().filter(|o| o.price > 0) {
+            let tick = math::tick_at_price(o.price, 0, 0)?;
+            if let Some(p) = prev {
+                let r = tick.abs_diff(p) as u128;
+                let sq = r * r;
+                var = Some(match var {
+                    Some(v) => (sq * VOL_EWMA_ALPHA_BPS + v * (10_000 - VOL_EWMA_ALPHA_BPS)) / 10_000,
+                    None => sq,
+                });
+            }
+            prev = Some(tick);
+        }
+        var.map(|v| math::isqrt(v) as u64)
+    }
+
+    /// Cumulative price at `ts`, assuming each price holds until the next
+    /// observation. `None` if `ts` predates the history.
+    fn cumulative_at(&self, ts: i64) -> Option<u128> {
+        let obs = self.iter().filter(|o| o.timestamp <= ts).last()?;
+        Some(obs.cumulative + obs.price as u128 * (ts - obs.timestamp) as u128)
+    }
+
+    /// Time-weighted average price over the `window_sec` seconds before `now`.
+    pub fn twap(&self, now: i64, window_sec: u64) -> Option<u64> {
+        if window_sec == 0 {
+            return None;
+        }
+        let end = self.cumulative_at(now)?;
+        let start = self.cumulative_at(now.checked_sub(window_sec as i64)?)?;
+        u64::try_from((end - start) / window_sec as u128).ok()
+    }
+}
+
+/// TWAP of the vault's price history over `vault.twap_window_sec`.
+pub fn read_twap_price(info: &AccountInfo, vault: &Vault, now: i64) -> Result<u64> {
+    require_keys_eq!(*info.key, vault.price_history, VaultError::OracleFeedMismatch);
+    require_keys_eq!(*info.owner, crate::ID, VaultError::InvalidOracle);
+    let history = PriceHistory::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+    history
+        .twap(now, vault.twap_window_sec)
+        .ok_or(error!(VaultError::InsufficientPriceHistory))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(min_interval_sec: u64) -> PriceHistory {
+        PriceHistory {
+            vault: Pubkey::default(),
+            min_interval_sec,
+            head: 0,
+            len: 0,
+            observations: [PriceObservation::default(); PRICE_HISTORY_LEN],
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn twap_weights_prices_by_time() {
+        let mut h = history(1);
+        h.record(100, 10).unwrap();
+        h.record(130, 20).unwrap();
+        // 10 held for 30s, 20 held for 10s
+        assert_eq!(h.twap(140, 40), Some((10 * 30 + 20 * 10) / 40));
+        // window ending before the latest observation still interpolates
+        assert_eq!(h.twap(130, 20), Some(10));
+        assert_eq!(h.twap(140, 41), None);
+        assert_eq!(h.twap(140, 0), None);
+    }
+
+    #[test]
+    fn single_slot_spike_barely_moves_twap() {
+        let mut h = history(1);
+        h.record(0, 1_000).unwrap();
+        h.record(599, 1_000_000).unwrap();
+        assert_eq!(h.twap(600, 600), Some((1_000 * 599 + 1_000_000) / 600));
+    }
+
+    #[test]
+    fn enforces_min_interval() {
+        let mut h = history(10);
+        h.record(100, 1).unwrap();
+        assert_eq!(h.record(109, 1), err!(VaultError::PriceRecordedTooSoon));
+        h.record(110, 1).unwrap();
+    }
+
+    #[test]
+    fn volatility_tracks_log_returns() {
+        let mut h = history(1);
+        h.record(0, 1_000_000).unwrap();
+        assert_eq!(h.ewma_volatility_bps(), None);
+        // alternating +-1%: ~100 ticks per observation whatever the price level
+        for i in 1..20 {
+            let p = if i % 2 == 1 { 1_010_000 } else { 1_000_000 };
+            h.record(i, p).unwrap();
+        }
+        let vol = h.ewma_volatility_bps().unwrap();
+        assert!((99..=100).contains(&vol), "{vol}");
+
+        let mut flat = history(1);
+        for i in 0..10 {
+            flat.record(i, 50_000_000_000).unwrap();
+        }
+        assert_eq!(flat.ewma_volatility_bps(), Some(0));
+    }
+
+    #[test]
+    fn ring_overwrites_oldest() {
+        let mut h = history(1);
+        for i in 0..(PRICE_HISTORY_LEN as i64 + 5) {
+            h.record(i * 10, i as u64).unwrap();
+        }
+        assert_eq!(h.len as usize, PRICE_HISTORY_LEN);
+        assert_eq!(h.iter().next().unwrap().timestamp, 50);
+        assert_eq!(h.newest().unwrap().price, PRICE_HISTORY_LEN as u64 + 4);
+        assert_eq!(h.twap(h.newest().unwrap().timestamp, 10 * PRICE_HISTORY_LEN as u64), None);
+        assert!(h.twap(h.newest().unwrap().timestamp, 10 * (PRICE_HISTORY_LEN as u64 - 1)).is_some());
+    }
+}
@@ -0,0 +1,303 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_spl::token;
+
+use crate::ladder::{self, BandAllocation};
+use crate::{BandAllocationChanged, Vault, VaultError};
+
+mod openbook;
+mod whirlpool;
+
+pub use openbook::{init_open_orders, open_orders_amounts, ClobState, OpenBookVenue, MAX_QUOTE_LEVELS};
+pub use whirlpool::{position_amounts, WhirlpoolVenue};
+
+/// Where the vault's liquidity is deployed on `rebalance`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VenueKind {
+    /// Tokens stay in `base_vault` / `quote_vault`.
+    Idle,
+    /// Orca Whirlpool concentrated liquidity position.
+    Whirlpool,
+    /// Bid/ask ladder resting on an OpenBook v2 market.
+    OpenBookV2,
+}
+
+/// Concentrated liquidity position owned by the vault PDA, one per band.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LpPosition {
+    pub band:          i64,
+    pub position:      Pubkey,
+    pub position_mint: Pubkey,
+    /// Paid the position's rent and gets it back when it is closed.
+    pub funder:        Pubkey,
+    pub tick_lower:    i32,
+    pub tick_upper:    i32,
+    pub liquidity:     u128,
+}
+
+/// What `harvest` brought into the vault, gross of the protocol's cut, and
+/// what it put back into the active band.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HarvestResult {
+    pub base:             u64,
+    pub quote:            u64,
+    pub protocol_base:    u64,
+    pub protocol_quote:   u64,
+    pub compounded_base:  u64,
+    pub compounded_quote: u64,
+}
+
+/// Accounts every venue needs. Venue-specific accounts are passed through
+/// `remaining_accounts` in the order documented on each adapter.
+pub struct VenueAccounts<'a, 'info> {
+    pub vault:         AccountInfo<'info>,
+    pub base_vault:    AccountInfo<'info>,
+    pub quote_vault:   AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub remaining:     &'a [AccountInfo<'info>],
+    pub signer_seeds:  &'a [&'a [&'a [u8]]],
+}
+
+impl<'a, 'info> VenueAccounts<'a, 'info> {
+    /// Idle balances that can be deployed, net of fees owed to the treasury
+    /// and of what the rebalance auction is offering.
+    pub fn deployable_balances(&self, vault: &Vault) -> Result<(u64, u64)> {
+        let base = token::accessor::amount(&self.base_vault)?;
+        let quote = token::accessor::amount(&self.quote_vault)?;
+        let (auction_base, auction_quote) = match vault.auction {
+            Some(a) if a.sell_base => (a.remaining, 0),
+            Some(a) => (0, a.remaining),
+            None => (0, 0),
+        };
+        Ok((
+            base.saturating_sub(vault.accrued_fee_base).saturating_sub(auction_base),
+            quote.saturating_sub(vault.accrued_fee_quote).saturating_sub(auction_quote),
+        ))
+    }
+}
+
+/// A DEX the vault can provide liquidity on. Adapters hold their own
+/// accounts and sign with the vault PDA.
+pub trait LiquidityVenue<'info> {
+    /// Pulls every open position back into `base_vault` / `quote_vault`.
+    fn withdraw(&mut self, vault: &mut Vault) -> Result<()>;
+    /// Pulls the liquidity deployed in `band` back into the vault.
+    /// Returns `true` if the venue had to pull every band instead.
+    fn withdraw_band(&mut self, vault: &mut Vault, band: i64) -> Result<bool>;
+    /// Deploys `base_amount` / `quote_amount` in `band`.
+    fn deploy(&mut self, vault: &mut Vault, band: i64, base_amount: u64, quote_amount: u64) -> Result<()>;
+    /// Pulls `shares / supply` of the open position back into the vault.
+    /// Returns `true` if the venue had to pull the whole position instead.
+    fn reduce(&mut self, vault: &mut Vault, shares: u64, supply: u64) -> Result<bool>;
+    /// Collects fees and rewards from every open position into the vault,
+    /// accruing the protocol's cut, and re-deposits the rest into `band`.
+    fn harvest(&mut self, vault: &mut Vault, band: i64) -> Result<HarvestResult>;
+}
+
+pub struct IdleVenue;
+
+impl<'info> LiquidityVenue<'info> for IdleVenue {
+    fn withdraw(&mut self, _vault: &mut Vault) -> Result<()> {
+        Ok(())
+    }
+
+    fn withdraw_band(&mut self, _vault: &mut Vault, _band: i64) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn deploy(&mut self, _vault: &mut Vault, _band: i64, _base_amount: u64, _quote_amount: u64) -> Result<()> {
+        Ok(())
+    }
+
+    fn reduce(&mut self, _vault: &mut Vault, _shares: u64, _supply: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn harvest(&mut self, _vault: &mut Vault, _band: i64) -> Result<HarvestResult> {
+        Ok(HarvestResult::default())
+    }
+}
+
+/// Moves the vault's ladder to be centred on `new_band`. `withdrawn` runs
+/// once the bands leaving the ladder are back in the vault, before anything
+/// is deployed.
+pub fn move_liquidity<'a, 'info>(
+    vault: &mut Vault,
+    accounts: &VenueAccounts<'a, 'info>,
+    new_band: i64,
+    withdrawn: impl FnOnce(&mut Vault) -> Result<()>,
+) -> Result<()> {
+    match vault.venue {
+        VenueKind::Idle => shift(&mut IdleVenue, vault, accounts, new_band, withdrawn),
+        VenueKind::Whirlpool => {
+            let mut venue = WhirlpoolVenue::load(vault, accounts)?;
+            shift(&mut venue, vault, accounts, new_band, withdrawn)
+        }
+        VenueKind::OpenBookV2 => {
+            let mut venue = OpenBookVenue::load(vault, accounts)?;
+            shift(&mut venue, vault, accounts, new_band, withdrawn)
+        }
+    }
+}
+
+/// Pulls a withdrawing holder's share of the open position into the vault.
+/// Returns `true` if the whole position was pulled instead.
+pub fn reduce_liquidity<'a, 'info>(
+    vault: &mut Vault,
+    accounts: &VenueAccounts<'a, 'info>,
+    shares: u64,
+    supply: u64,
+) -> Result<bool> {
+    let pulled_all = match vault.venue {
+        VenueKind::Idle => IdleVenue.reduce(vault, shares, supply)?,
+        VenueKind::Whirlpool => WhirlpoolVenue::load(vault, accounts)?.reduce(vault, shares, supply)?,
+        VenueKind::OpenBookV2 => OpenBookVenue::load(vault, accounts)?.reduce(vault, shares, supply)?,
+    };
+    if pulled_all {
+        // nothing is deployed any more, so the next rebalance fills every band
+        vault.ladder.allocations.clear();
+    }
+    Ok(pulled_all)
+}
+
+/// Collects the venue's fees and rewards and compounds the holders' part
+/// into `band`.
+pub fn harvest<'a, 'info>(vault: &mut Vault, accounts: &VenueAccounts<'a, 'info>, band: i64) -> Result<HarvestResult> {
+    match vault.venue {
+        VenueKind::Idle => IdleVenue.harvest(vault, band),
+        VenueKind::Whirlpool => WhirlpoolVenue::load(vault, accounts)?.harvest(vault, band),
+        VenueKind::OpenBookV2 => OpenBookVenue::load(vault, accounts)?.harvest(vault, band),
+    }
+}
+
+/// Pulls filled proceeds from an order-book venue into the vault.
+pub fn settle_funds<'a, 'info>(vault: &mut Vault, accounts: &VenueAccounts<'a, 'info>) -> Result<()> {
+    require!(vault.venue == VenueKind::OpenBookV2, VaultError::InvalidVenueAccounts);
+    OpenBookVenue::load(vault, accounts)?.settle()
+}
+
+/// Base and quote the vault has out on its venue, including fees earned
+/// there but not yet collected, read from the head of `accounts` (see
+/// `position_amounts` and `open_orders_amounts`). Positions are split at
+/// `price` when given. Returns the amounts and the number of accounts read.
+pub fn deployed_amounts(vault: &Vault, accounts: &[AccountInfo], price: Option<u64>) -> Result<((u64, u64), usize)> {
+    match vault.venue {
+        VenueKind::Idle => Ok(((0, 0), 0)),
+        VenueKind::Whirlpool => position_amounts(vault, accounts, price),
+        VenueKind::OpenBookV2 => open_orders_amounts(vault, accounts),
+    }
+}
+
+/// Whether the vault has liquidity or orders out on its venue.
+pub fn has_open_position(vault: &Vault) -> bool {
+    !vault.positions.is_empty() || !vault.clob.open_order_ids.is_empty()
+}
+
+/// Re-centres the ladder on `new_band`. Only bands that fell out of the
+/// window are pulled, and the freed balances fund the bands that entered it:
+/// base goes to bands at or above the centre, quote to bands at or below it.
+/// When the centre is unchanged, the band width has changed or the venue
+/// cannot pull single bands, every band is redeployed.
+fn shift<'a, 'info, V: LiquidityVenue<'info>>(
+    venue: &mut V,
+    vault: &mut Vault,
+    accounts: &VenueAccounts<'a, 'info>,
+    new_band: i64,
+    withdrawn: impl FnOnce(&mut Vault) -> Result<()>,
+) -> Result<()> {
+    let old = std::mem::take(&mut vault.ladder.allocations);
+    let table = vault.ladder.allocation_table(new_band);
+    let band_bp = vault.effective_band_bp;
+    let mut rebuild = vault.ladder.needs_rebuild(new_band, band_bp);
+    if rebuild {
+        venue.withdraw(vault)?;
+    } else {
+        for a in &old {
+            if ladder::weight_of(&table, a.band) == 0 && venue.withdraw_band(vault, a.band)? {
+                rebuild = true;
+                break;
+            }
+        }
+    }
+    withdrawn(vault)?;
+
+    let fresh: Vec<BandAllocation> = table
+        .iter()
+        .filter(|a| rebuild || ladder::weight_of(&old, a.band) == 0)
+        .copied()
+        .collect();
+    let ask_weight: u64 = fresh.iter().filter(|a| a.band >= new_band).map(|a| a.weight_bps as u64).sum();
+    let bid_weight: u64 = fresh.iter().filter(|a| a.band <= new_band).map(|a| a.weight_bps as u64).sum();
+    let (base, quote) = accounts.deployable_balances(vault)?;
+    for a in &fresh {
+        let base_amount = if a.band >= new_band { split(base, a.weight_bps, ask_weight)? } else { 0 };
+        let quote_amount = if a.band <= new_band { split(quote, a.weight_bps, bid_weight)? } else { 0 };
+        venue.deploy(vault, a.band, base_amount, quote_amount)?;
+    }
+
+    for a in old.iter().filter(|a| ladder::weight_of(&table, a.band) == 0).chain(&table) {
+        let old_weight_bps = ladder::weight_of(&old, a.band);
+        let new_weight_bps = ladder::weight_of(&table, a.band);
+        if old_weight_bps != new_weight_bps {
+            emit!(BandAllocationChanged { band: a.band, old_weight_bps, new_weight_bps });
+        }
+    }
+    vault.ladder.center = new_band;
+    vault.ladder.band_bp = band_bp;
+    vault.ladder.allocations = table;
+    Ok(())
+}
+
+/// `amount * weight / total`.
+fn split(amount: u64, weight: u16, total: u64) -> Result<u64> {
+    let share = crate::math::mul_div(amount as u128, weight as u128, total as u128).ok_or(VaultError::MathOverflow)?;
+    Ok(share as u64)
+}
+
+/// Takes the next venue account, optionally checking its key.
+pub(crate) fn next_account<'a, 'info>(
+    iter: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    expected: Option<&Pubkey>,
+) -> Result<&'a AccountInfo<'info>> {
+    let acc = iter.next().ok_or(VaultError::InvalidVenueAccounts)?;
+    if let Some(key) = expected {
+        require_keys_eq!(*acc.key, *key, VaultError::InvalidVenueAccounts);
+    }
+    Ok(acc)
+}
+
+pub(crate) fn read_pubkey(data: &[u8], offset: usize) -> Pubkey {
+    Pubkey::new_from_array(data[offset..offset + 32].try_into().unwrap())
+}
+
+pub(crate) fn read_u128(data: &[u8], offset: usize) -> u128 {
+    u128::from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+/// Invokes a venue program; `metas` are `(account, writable)` and the vault
+/// PDA is signed for with the vault seeds.
+pub(crate) fn invoke_venue<'info>(
+    program: &AccountInfo<'info>,
+    vault: &Pubkey,
+    signer_seeds: &[&[&[u8]]],
+    data: Vec<u8>,
+    metas: &[(&AccountInfo<'info>, bool)],
+) -> Result<()> {
+    let ix = Instruction {
+        program_id: *program.key,
+        accounts: metas
+            .iter()
+            .map(|(acc, writable)| AccountMeta {
+                pubkey: *acc.key,
+                is_signer: acc.is_signer || acc.key == vault,
+                is_writable: *writable,
+            })
+            .collect(),
+        data,
+    };
+    let mut infos: Vec<AccountInfo<'info>> = metas.iter().map(|(acc, _)| (*acc).clone()).collect();
+    infos.push(program.clone());
+    invoke_signed(&ix, &infos, signer_seeds)?;
+    Ok(())
+}
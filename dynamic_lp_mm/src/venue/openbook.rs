@@ -0,0 +1,424 @@
+use anchor_lang::prelude::*;
+
+use super::{invoke_venue, next_account, HarvestResult, LiquidityVenue, VenueAccounts};
+use crate::math;
+use crate::{Vault, VaultError};
+
+const IX_PLACE_ORDER: [u8; 8] = [51, 194, 155, 175, 109, 130, 96, 106];
+const IX_CANCEL_ALL_ORDERS: [u8; 8] = [196, 83, 243, 171, 17, 100, 160, 143];
+const IX_CANCEL_ORDER_BY_CLIENT_ORDER_ID: [u8; 8] = [115, 178, 201, 8, 175, 183, 123, 119];
+const IX_SETTLE_FUNDS: [u8; 8] = [238, 64, 163, 96, 75, 171, 16, 33];
+const IX_CREATE_OPEN_ORDERS_INDEXER: [u8; 8] = [64, 64, 153, 255, 217, 71, 249, 133];
+const IX_CREATE_OPEN_ORDERS_ACCOUNT: [u8; 8] = [204, 181, 175, 222, 40, 125, 188, 71];
+
+const SIDE_BID: u8 = 0;
+const SIDE_ASK: u8 = 1;
+const ORDER_TYPE_POST_ONLY: u8 = 2;
+const SELF_TRADE_DECREMENT_TAKE: u8 = 0;
+const MATCH_LIMIT: u8 = 10;
+
+pub const MAX_QUOTE_LEVELS: u8 = 5;
+
+// `OpenOrdersAccount.position` field offsets, including the discriminator
+const OO_ASKS_BASE_LOTS: usize = 152;
+const OO_BASE_FREE_NATIVE: usize = 160;
+const OO_QUOTE_FREE_NATIVE: usize = 168;
+const OO_BIDS_QUOTE_LOTS: usize = 232;
+// `OpenOrdersAccount.open_orders` slots and `OpenOrder` field offsets
+const OO_OPEN_ORDERS: usize = 304;
+const OO_OPEN_ORDER_LEN: usize = 72;
+const OO_MAX_OPEN_ORDERS: usize = 24;
+const ORDER_CLIENT_ID: usize = 16;
+const ORDER_IS_FREE: usize = 32;
+const ORDER_SIDE_AND_TREE: usize = 33;
+
+/// Resting orders and quoting parameters for an order-book venue.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ClobState {
+    pub open_orders:          Pubkey,
+    pub base_lot_size:        u64,
+    pub quote_lot_size:       u64,
+    pub spread_bps:           u16,
+    pub level_step_bps:       u16,
+    pub levels:               u8,
+    pub order_size:           u64,
+    pub next_client_order_id: u64,
+    pub open_order_ids:       Vec<u64>,
+}
+
+/// OpenBook v2 adapter. `remaining_accounts`: openbook program, market,
+/// open orders account, bids, asks, event heap, market authority, market
+/// base vault, market quote vault, penalty payer (signer), system program.
+pub struct OpenBookVenue<'a, 'info> {
+    common:             &'a VenueAccounts<'a, 'info>,
+    program:            &'a AccountInfo<'info>,
+    market:             &'a AccountInfo<'info>,
+    open_orders:        &'a AccountInfo<'info>,
+    bids:               &'a AccountInfo<'info>,
+    asks:               &'a AccountInfo<'info>,
+    event_heap:         &'a AccountInfo<'info>,
+    market_authority:   &'a AccountInfo<'info>,
+    market_base_vault:  &'a AccountInfo<'info>,
+    market_quote_vault: &'a AccountInfo<'info>,
+    penalty_payer:      &'a AccountInfo<'info>,
+    system_program:     &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> OpenBookVenue<'a, 'info> {
+    pub fn load(vault: &Vault, common: &'a VenueAccounts<'a, 'info>) -> Result<Self> {
+        require_keys_neq!(vault.clob.open_orders, Pubkey::default(), VaultError::InvalidVenueAccounts);
+        let mut rest = common.remaining.iter();
+        Ok(Self {
+            common,
+            program:            next_account(&mut rest, Some(&vault.venue_program))?,
+            market:             next_account(&mut rest, Some(&vault.venue_market))?,
+            open_orders:        next_account(&mut rest, Some(&vault.clob.open_orders))?,
+            bids:               next_account(&mut rest, None)?,
+            asks:               next_account(&mut rest, None)?,
+            event_heap:         next_account(&mut rest, None)?,
+            market_authority:   next_account(&mut rest, None)?,
+            market_base_vault:  next_account(&mut rest, None)?,
+            market_quote_vault: next_account(&mut rest, None)?,
+            penalty_payer:      next_account(&mut rest, None)?,
+            system_program:     next_account(&mut rest, None)?,
+        })
+    }
+
+    fn invoke(&self, data: Vec<u8>, metas: &[(&AccountInfo<'info>, bool)]) -> Result<()> {
+        invoke_venue(self.program, self.common.vault.key, self.common.signer_seeds, data, metas)
+    }
+
+    fn cancel_all(&self) -> Result<()> {
+        let mut data = IX_CANCEL_ALL_ORDERS.to_vec();
+        data.push(0); // side_option: None
+        data.push(u8::MAX); // limit
+        self.invoke(
+            data,
+            &[
+                (&self.common.vault, false),
+                (self.open_orders, true),
+                (self.market, false),
+                (self.bids, true),
+                (self.asks, true),
+            ],
+        )
+    }
+
+    fn cancel(&self, client_order_id: u64) -> Result<()> {
+        let mut data = IX_CANCEL_ORDER_BY_CLIENT_ORDER_ID.to_vec();
+        data.extend_from_slice(&client_order_id.to_le_bytes());
+        self.invoke(
+            data,
+            &[
+                (&self.common.vault, false),
+                (self.open_orders, true),
+                (self.market, false),
+                (self.bids, true),
+                (self.asks, true),
+            ],
+        )
+    }
+
+    /// Moves free balances on the open orders account into the vault.
+    pub fn settle(&self) -> Result<()> {
+        self.invoke(
+            IX_SETTLE_FUNDS.to_vec(),
+            &[
+                (&self.common.vault, true),
+                (self.penalty_payer, true),
+                (self.open_orders, true),
+                (self.market, true),
+                (self.market_authority, false),
+                (self.market_base_vault, true),
+                (self.market_quote_vault, true),
+                (&self.common.base_vault, true),
+                (&self.common.quote_vault, true),
+                (self.program, false), // referrer_account: None
+                (&self.common.token_program, false),
+                (self.system_program, false),
+            ],
+        )
+    }
+
+    fn place(&self, side: u8, price_lots: i64, base_lots: i64, client_order_id: u64) -> Result<()> {
+        let mut data = IX_PLACE_ORDER.to_vec();
+        data.push(side);
+        data.extend_from_slice(&price_lots.to_le_bytes());
+        data.extend_from_slice(&base_lots.to_le_bytes());
+        data.extend_from_slice(&i64::MAX.to_le_bytes()); // max_quote_lots_including_fees
+        data.extend_from_slice(&client_order_id.to_le_bytes());
+        data.push(ORDER_TYPE_POST_ONLY);
+        data.extend_from_slice(&0u64.to_le_bytes()); // expiry_timestamp
+        data.push(SELF_TRADE_DECREMENT_TAKE);
+        data.push(MATCH_LIMIT);
+        let (user_token_account, market_vault) = if side == SIDE_BID {
+            (&self.common.quote_vault, self.market_quote_vault)
+        } else {
+            (&self.common.base_vault, self.market_base_vault)
+        };
+        self.invoke(
+            data,
+            &[
+                (&self.common.vault, false),
+                (self.open_orders, true),
+                (self.program, false), // open_orders_admin: None
+                (user_token_account, true),
+                (self.market, true),
+                (self.bids, true),
+                (self.asks, true),
+                (self.event_heap, true),
+                (market_vault, true),
+                (self.program, false), // oracle_a: None
+                (self.program, false), // oracle_b: None
+                (&self.common.token_program, false),
+            ],
+        )
+    }
+}
+
+/// Vault price converted to quote lots per base lot.
+fn price_lots(vault: &Vault, price: u64) -> Option<i64> {
+    let num = 10u128
+        .checked_pow(vault.quote_decimals as u32)?
+        .checked_mul(vault.clob.base_lot_size as u128)?;
+    let den = 10u128
+        .checked_pow(vault.base_decimals as u32)?
+        .checked_mul(math::PRICE_SCALE as u128)?
+        .checked_mul(vault.clob.quote_lot_size as u128)?;
+    i64::try_from(math::mul_div(price as u128, num, den)?).ok()
+}
+
+impl<'a, 'info> LiquidityVenue<'info> for OpenBookVenue<'a, 'info> {
+    fn withdraw(&mut self, vault: &mut Vault) -> Result<()> {
+        self.cancel_all()?;
+        self.settle()?;
+        vault.clob.open_order_ids.clear();
+        Ok(())
+    }
+
+    fn withdraw_band(&mut self, vault: &mut Vault, _band: i64) -> Result<bool> {
+        // orders are not tracked per band, so pull the whole book
+        self.withdraw(vault)?;
+        Ok(true)
+    }
+
+    fn deploy(&mut self, vault: &mut Vault, band: i64, base_amount: u64, quote_amount: u64) -> Result<()> {
+        let (lo, hi) = math::band_price_bounds(band, vault.effective_band_bp).ok_or(VaultError::MathOverflow)?;
+        let mid = lo + (hi - lo) / 2;
+        let c = vault.clob.clone();
+        let base_lots = c.order_size / c.base_lot_size.max(1);
+        if base_lots == 0 {
+            return Ok(());
+        }
+        let base_per_order = base_lots * c.base_lot_size;
+
+        let mut base_left = base_amount;
+        let mut quote_left = quote_amount;
+        for level in 0..c.levels as u64 {
+            let offset = c.spread_bps as u64 + level * c.level_step_bps as u64;
+            let ask = mid.checked_mul(10_000 + offset).ok_or(VaultError::MathOverflow)? / 10_000;
+            let ask_lots = price_lots(vault, ask).ok_or(VaultError::MathOverflow)?;
+            if ask_lots > 0 && base_left >= base_per_order {
+                let id = vault.clob.next_client_order_id;
+                self.place(SIDE_ASK, ask_lots, base_lots as i64, id)?;
+                vault.clob.next_client_order_id = id.checked_add(1).ok_or(VaultError::MathOverflow)?;
+                vault.clob.open_order_ids.push(id);
+                base_left -= base_per_order;
+            }
+
+            let bid = mid.checked_mul(10_000u64.saturating_sub(offset)).ok_or(VaultError::MathOverflow)? / 10_000;
+            let bid_lots = price_lots(vault, bid).ok_or(VaultError::MathOverflow)?;
+            let quote_needed = (bid_lots as u64)
+                .checked_mul(base_lots)
+                .and_then(|q| q.checked_mul(c.quote_lot_size))
+                .ok_or(VaultError::MathOverflow)?;
+            if bid_lots > 0 && quote_left >= quote_needed {
+                let id = vault.clob.next_client_order_id;
+                self.place(SIDE_BID, bid_lots, base_lots as i64, id)?;
+                vault.clob.next_client_order_id = id.checked_add(1).ok_or(VaultError::MathOverflow)?;
+                vault.clob.open_order_ids.push(id);
+                quote_left -= quote_needed;
+            }
+        }
+        Ok(())
+    }
+
+    fn reduce(&mut self, vault: &mut Vault, shares: u64, supply: u64) -> Result<bool> {
+        // orders cannot be split, so cancel whole ones, outermost levels
+        // first, until what stays locked is at most the other holders' part
+        let keep = |lots: u64| {
+            math::mul_div(lots as u128, supply.saturating_sub(shares) as u128, supply.max(1) as u128)
+                .ok_or(VaultError::MathOverflow)
+        };
+        let before = locked_lots(&self.open_orders.try_borrow_data()?)?;
+        let (keep_asks, keep_bids) = (keep(before.0)?, keep(before.1)?);
+        for i in (0..vault.clob.open_order_ids.len()).rev() {
+            let id = vault.clob.open_order_ids[i];
+            let (side, (asks, bids)) = {
+                let data = self.open_orders.try_borrow_data()?;
+                (resting_side(&data, id), locked_lots(&data)?)
+            };
+            let (asks_over, bids_over) = (asks as u128 > keep_asks, bids as u128 > keep_bids);
+            if !asks_over && !bids_over {
+                break;
+            }
+            match side {
+                // filled since it was placed
+                None => {}
+                Some(SIDE_ASK) if asks_over => self.cancel(id)?,
+                Some(SIDE_BID) if bids_over => self.cancel(id)?,
+                Some(_) => continue,
+            }
+            vault.clob.open_order_ids.remove(i);
+        }
+        self.settle()?;
+        Ok(vault.clob.open_order_ids.is_empty())
+    }
+
+    fn harvest(&mut self, _vault: &mut Vault, _band: i64) -> Result<HarvestResult> {
+        // resting orders earn no fees, and fills come back through `settle`
+        Ok(HarvestResult::default())
+    }
+}
+
+/// Base lots locked in asks and quote lots locked in bids on an open
+/// orders account.
+fn locked_lots(data: &[u8]) -> Result<(u64, u64)> {
+    require!(data.len() >= OO_BIDS_QUOTE_LOTS + 8, VaultError::InvalidVenueAccounts);
+    let u64_at = |o: usize| u64::from_le_bytes(data[o..o + 8].try_into().unwrap());
+    Ok((u64_at(OO_ASKS_BASE_LOTS), u64_at(OO_BIDS_QUOTE_LOTS)))
+}
+
+/// Side of the order resting as `client_order_id` on an open orders
+/// account, `None` once it has been filled or cancelled.
+fn resting_side(data: &[u8], client_order_id: u64) -> Option<u8> {
+    (0..OO_MAX_OPEN_ORDERS)
+        .map(|i| OO_OPEN_ORDERS + i * OO_OPEN_ORDER_LEN)
+        .filter_map(|o| data.get(o..o + OO_OPEN_ORDER_LEN))
+        .find(|order| {
+            order[ORDER_IS_FREE] == 0
+                && u64::from_le_bytes(order[ORDER_CLIENT_ID..ORDER_CLIENT_ID + 8].try_into().unwrap())
+                    == client_order_id
+        })
+        // oracle-pegged orders use 2 and 3
+        .map(|order| order[ORDER_SIDE_AND_TREE] % 2)
+}
+
+/// Base and quote on the vault's open orders account: free balances plus
+/// what resting orders lock up. `accounts` start with the open orders
+/// account. Returns the amounts and the number of accounts read.
+pub fn open_orders_amounts(vault: &Vault, accounts: &[AccountInfo]) -> Result<((u64, u64), usize)> {
+    if vault.clob.open_orders == Pubkey::default() {
+        return Ok(((0, 0), 0));
+    }
+    let open_orders = next_account(&mut accounts.iter(), Some(&vault.clob.open_orders))?;
+    require_keys_eq!(*open_orders.owner, vault.venue_program, VaultError::InvalidVenueAccounts);
+    let data = open_orders.try_borrow_data()?;
+    let (asks_base_lots, bids_quote_lots) = locked_lots(&data)?;
+    let u64_at = |o: usize| u64::from_le_bytes(data[o..o + 8].try_into().unwrap());
+    let base = (asks_base_lots as u128 * vault.clob.base_lot_size as u128)
+        .checked_add(u64_at(OO_BASE_FREE_NATIVE) as u128)
+        .and_then(|x| u64::try_from(x).ok())
+        .ok_or(VaultError::MathOverflow)?;
+    let quote = (bids_quote_lots as u128 * vault.clob.quote_lot_size as u128)
+        .checked_add(u64_at(OO_QUOTE_FREE_NATIVE) as u128)
+        .and_then(|x| u64::try_from(x).ok())
+        .ok_or(VaultError::MathOverflow)?;
+    Ok(((base, quote), 1))
+}
+
+/// Creates the vault-owned open orders account (and the owner's indexer on
+/// first use). `remaining_accounts`: openbook program, open orders indexer,
+/// open orders account, market, rent.
+pub fn init_open_orders<'info>(
+    vault: &mut Vault,
+    vault_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    remaining: &[AccountInfo<'info>],
+    signer_seeds: &[&[&[u8]]],
+    name: String,
+) -> Result<()> {
+    let mut rest = remaining.iter();
+    let program = next_account(&mut rest, Some(&vault.venue_program))?;
+    let indexer = next_account(&mut rest, None)?;
+    let open_orders = next_account(&mut rest, None)?;
+    let market = next_account(&mut rest, Some(&vault.venue_market))?;
+    let rent = next_account(&mut rest, None)?;
+
+    if indexer.data_is_empty() {
+        invoke_venue(
+            program,
+            vault_info.key,
+            signer_seeds,
+            IX_CREATE_OPEN_ORDERS_INDEXER.to_vec(),
+            &[
+                (payer, true),
+                (vault_info, false),
+                (indexer, true),
+                (system_program, false),
+                (rent, false),
+            ],
+        )?;
+    }
+    let mut data = IX_CREATE_OPEN_ORDERS_ACCOUNT.to_vec();
+    name.serialize(&mut data)?;
+    invoke_venue(
+        program,
+        vault_info.key,
+        signer_seeds,
+        data,
+        &[
+            (payer, true),
+            (vault_info, false),
+            (program, false), // delegate_account: None
+            (indexer, true),
+            (open_orders, true),
+            (market, false),
+            (system_program, false),
+        ],
+    )?;
+
+    vault.clob.open_orders = *open_orders.key;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_orders(orders: &[(u64, u8)], asks_base_lots: u64, bids_quote_lots: u64) -> Vec<u8> {
+        let mut data = vec![0u8; OO_OPEN_ORDERS + OO_MAX_OPEN_ORDERS * OO_OPEN_ORDER_LEN];
+        data[OO_ASKS_BASE_LOTS..OO_ASKS_BASE_LOTS + 8].copy_from_slice(&asks_base_lots.to_le_bytes());
+        data[OO_BIDS_QUOTE_LOTS..OO_BIDS_QUOTE_LOTS + 8].copy_from_slice(&bids_quote_lots.to_le_bytes());
+        for slot in 0..OO_MAX_OPEN_ORDERS {
+            data[OO_OPEN_ORDERS + slot * OO_OPEN_ORDER_LEN + ORDER_IS_FREE] = 1;
+        }
+        for (slot, (client_id, side)) in orders.iter().enumerate() {
+            let order = &mut data[OO_OPEN_ORDERS + slot * OO_OPEN_ORDER_LEN..][..OO_OPEN_ORDER_LEN];
+            order[ORDER_CLIENT_ID..ORDER_CLIENT_ID + 8].copy_from_slice(&client_id.to_le_bytes());
+            order[ORDER_IS_FREE] = 0;
+            order[ORDER_SIDE_AND_TREE] = *side;
+        }
+        data
+    }
+
+    #[test]
+    fn finds_resting_orders_by_client_id() {
+        let data = open_orders(&[(7, SIDE_ASK), (8, SIDE_BID), (9, SIDE_ASK + 2)], 0, 0);
+        assert_eq!(resting_side(&data, 7), Some(SIDE_ASK));
+        assert_eq!(resting_side(&data, 8), Some(SIDE_BID));
+        // oracle-pegged tree
+        assert_eq!(resting_side(&data, 9), Some(SIDE_ASK));
+        // filled orders free their slot
+        assert_eq!(resting_side(&data, 10), None);
+        assert_eq!(resting_side(&data, 0), None);
+    }
+
+    #[test]
+    fn reads_locked_lots() {
+        let data = open_orders(&[], 30, 4_500);
+        assert_eq!(locked_lots(&data).unwrap(), (30, 4_500));
+        assert!(locked_lots(&data[..OO_BIDS_QUOTE_LOTS]).is_err());
+    }
+}
@@ -0,0 +1,658 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token;
+
+use super::{invoke_venue, next_account, read_pubkey, read_u128, HarvestResult, LiquidityVenue, LpPosition, VenueAccounts};
+use crate::{fees, math};
+use crate::{Vault, VaultError};
+
+const WHIRLPOOL_DISCRIMINATOR: [u8; 8] = [63, 149, 209, 12, 225, 128, 99, 9];
+const IX_OPEN_POSITION: [u8; 8] = [135, 128, 47, 77, 15, 152, 240, 49];
+const IX_INCREASE_LIQUIDITY: [u8; 8] = [46, 156, 243, 118, 13, 205, 251, 178];
+const IX_DECREASE_LIQUIDITY: [u8; 8] = [160, 38, 208, 111, 104, 91, 44, 1];
+const IX_COLLECT_FEES: [u8; 8] = [164, 152, 207, 99, 30, 186, 19, 182];
+const IX_CLOSE_POSITION: [u8; 8] = [123, 134, 81, 0, 49, 68, 98, 98];
+const IX_UPDATE_FEES_AND_REWARDS: [u8; 8] = [154, 230, 250, 13, 236, 209, 75, 223];
+const IX_COLLECT_REWARD: [u8; 8] = [70, 5, 132, 87, 86, 235, 177, 34];
+
+// `Whirlpool` account field offsets, including the discriminator
+const WP_TICK_SPACING: usize = 41;
+const WP_SQRT_PRICE: usize = 65;
+const WP_TOKEN_MINT_A: usize = 101;
+const WP_TOKEN_VAULT_A: usize = 133;
+const WP_TOKEN_MINT_B: usize = 181;
+const WP_TOKEN_VAULT_B: usize = 213;
+const WP_REWARD_INFOS: usize = 269;
+const WP_REWARD_INFO_LEN: usize = 128;
+const WP_NUM_REWARDS: usize = 3;
+const WP_LEN: usize = 653;
+
+// `Position` account field offsets
+const POS_LIQUIDITY: usize = 72;
+const POS_FEE_OWED_A: usize = 112;
+const POS_FEE_OWED_B: usize = 136;
+const POS_LEN: usize = 216;
+
+/// Liquidity left undeployed so rounding differences against the pool's
+/// own tick math never exceed the token maximums.
+const LIQUIDITY_HEADROOM_BPS: u128 = 1;
+
+/// Whirlpool fields the adapter works from.
+struct Pool {
+    base_is_a:     bool,
+    tick_spacing:  u16,
+    sqrt_price:    u128,
+    token_vault_a: Pubkey,
+    token_vault_b: Pubkey,
+}
+
+impl Pool {
+    fn read(vault: &Vault, whirlpool: &AccountInfo) -> Result<Self> {
+        require_keys_eq!(*whirlpool.owner, vault.venue_program, VaultError::InvalidVenueAccounts);
+        let data = whirlpool.try_borrow_data()?;
+        require!(
+            data.len() >= WP_LEN && data[..8] == WHIRLPOOL_DISCRIMINATOR,
+            VaultError::InvalidVenueAccounts
+        );
+        let mint_a = read_pubkey(&data, WP_TOKEN_MINT_A);
+        let mint_b = read_pubkey(&data, WP_TOKEN_MINT_B);
+        let base_is_a = if mint_a == vault.base_mint && mint_b == vault.quote_mint {
+            true
+        } else if mint_a == vault.quote_mint && mint_b == vault.base_mint {
+            false
+        } else {
+            return err!(VaultError::InvalidVenueAccounts);
+        };
+        Ok(Self {
+            base_is_a,
+            tick_spacing: u16::from_le_bytes([data[WP_TICK_SPACING], data[WP_TICK_SPACING + 1]]),
+            sqrt_price: read_u128(&data, WP_SQRT_PRICE),
+            token_vault_a: read_pubkey(&data, WP_TOKEN_VAULT_A),
+            token_vault_b: read_pubkey(&data, WP_TOKEN_VAULT_B),
+        })
+    }
+}
+
+/// Base and quote held by the vault's positions, plus fees owed to them net
+/// of the protocol's cut. Liquidity is split at `price` (a vault price,
+/// normally the oracle's) so moving the pool inside a transaction cannot
+/// shift the split; without one, at the pool's current price, which is
+/// what pulling the liquidity returns. `accounts` start with the
+/// whirlpool, followed by every open position in `Vault::positions` order.
+/// Returns the amounts and the number of accounts read.
+pub fn position_amounts(vault: &Vault, accounts: &[AccountInfo], price: Option<u64>) -> Result<((u64, u64), usize)> {
+    if vault.positions.is_empty() {
+        return Ok(((0, 0), 0));
+    }
+    let mut rest = accounts.iter();
+    let pool = Pool::read(vault, next_account(&mut rest, Some(&vault.venue_market))?)?;
+    let sqrt_price = match price {
+        Some(price) => {
+            let base_sqrt = math::price_to_x64(price, vault.base_decimals, vault.quote_decimals)
+                .map(math::sqrt_price_x64)
+                .filter(|s| *s > 0)
+                .ok_or(VaultError::InvalidPrice)?;
+            if pool.base_is_a { base_sqrt } else { u128::MAX / base_sqrt }
+        }
+        None => pool.sqrt_price,
+    };
+    let (mut a, mut b) = (0u64, 0u64);
+    for open in &vault.positions {
+        let position = next_account(&mut rest, Some(&open.position))?;
+        require_keys_eq!(*position.owner, vault.venue_program, VaultError::InvalidVenueAccounts);
+        let data = position.try_borrow_data()?;
+        require!(data.len() >= POS_LEN, VaultError::InvalidVenueAccounts);
+        let (held_a, held_b) = math::amounts_for_liquidity(
+            sqrt_price,
+            math::sqrt_price_at_tick(open.tick_lower),
+            math::sqrt_price_at_tick(open.tick_upper),
+            read_u128(&data, POS_LIQUIDITY),
+        )
+        .ok_or(VaultError::MathOverflow)?;
+        // the protocol's cut is accrued for the treasury when the fees are collected
+        let fee_a = u64::from_le_bytes(data[POS_FEE_OWED_A..POS_FEE_OWED_A + 8].try_into().unwrap());
+        let fee_b = u64::from_le_bytes(data[POS_FEE_OWED_B..POS_FEE_OWED_B + 8].try_into().unwrap());
+        let fee_a = fee_a - fees::protocol_cut(fee_a, vault.fee_bps);
+        let fee_b = fee_b - fees::protocol_cut(fee_b, vault.fee_bps);
+        a = a.checked_add(held_a).and_then(|x| x.checked_add(fee_a)).ok_or(VaultError::MathOverflow)?;
+        b = b.checked_add(held_b).and_then(|x| x.checked_add(fee_b)).ok_or(VaultError::MathOverflow)?;
+    }
+    let amounts = if pool.base_is_a { (a, b) } else { (b, a) };
+    Ok((amounts, 1 + vault.positions.len()))
+}
+
+/// Orca Whirlpool adapter, one position per ladder band. `remaining_accounts`
+/// start with the whirlpool program, the whirlpool, and its token vaults A
+/// and B, followed by:
+/// - each band withdrawn, in `Vault::positions` order: position, position
+///   mint, position token account, tick array lower, tick array upper, the
+///   position's funder (receives its rent)
+/// - each band deployed, in ladder order: funder (signer), position,
+///   position mint (signer), position token account, tick array lower, tick
+///   array upper, system program, rent, associated token program
+/// - `reduce`, for every open position: position, position token account,
+///   tick array lower, tick array upper
+/// - `harvest`: the reward vault of every reward paid in the base or quote
+///   mint, in reward order, then for every open position: position, position
+///   token account, tick array lower, tick array upper
+pub struct WhirlpoolVenue<'a, 'info> {
+    common:        &'a VenueAccounts<'a, 'info>,
+    rest:          std::slice::Iter<'a, AccountInfo<'info>>,
+    program:       &'a AccountInfo<'info>,
+    whirlpool:     &'a AccountInfo<'info>,
+    token_vault_a: &'a AccountInfo<'info>,
+    token_vault_b: &'a AccountInfo<'info>,
+    base_is_a:     bool,
+    tick_spacing:  u16,
+    sqrt_price:    u128,
+}
+
+impl<'a, 'info> WhirlpoolVenue<'a, 'info> {
+    pub fn load(vault: &Vault, common: &'a VenueAccounts<'a, 'info>) -> Result<Self> {
+        let mut rest = common.remaining.iter();
+        let program = next_account(&mut rest, Some(&vault.venue_program))?;
+        let whirlpool = next_account(&mut rest, Some(&vault.venue_market))?;
+        let pool = Pool::read(vault, whirlpool)?;
+        let token_vault_a = next_account(&mut rest, Some(&pool.token_vault_a))?;
+        let token_vault_b = next_account(&mut rest, Some(&pool.token_vault_b))?;
+
+        Ok(Self {
+            common,
+            rest,
+            program,
+            whirlpool,
+            token_vault_a,
+            token_vault_b,
+            base_is_a: pool.base_is_a,
+            tick_spacing: pool.tick_spacing,
+            sqrt_price: pool.sqrt_price,
+        })
+    }
+
+    fn owner_account_a(&self) -> &AccountInfo<'info> {
+        if self.base_is_a { &self.common.base_vault } else { &self.common.quote_vault }
+    }
+
+    fn owner_account_b(&self) -> &AccountInfo<'info> {
+        if self.base_is_a { &self.common.quote_vault } else { &self.common.base_vault }
+    }
+
+    /// Pool tick range covering `band`, aligned to the pool's tick spacing.
+    fn tick_range(&self, vault: &Vault, band: i64) -> Result<(i32, i32)> {
+        let (lo, hi) = math::band_price_bounds(band, vault.effective_band_bp).ok_or(VaultError::MathOverflow)?;
+        let lo = math::tick_at_price(lo, vault.base_decimals, vault.quote_decimals).ok_or(VaultError::MathOverflow)?;
+        let hi = math::tick_at_price(hi, vault.base_decimals, vault.quote_decimals).ok_or(VaultError::MathOverflow)?;
+        let (lo, hi) = if self.base_is_a { (lo, hi) } else { (-hi, -lo) };
+        Ok(math::align_tick_range(lo, hi, self.tick_spacing))
+    }
+
+    fn invoke(&self, data: Vec<u8>, metas: &[(&AccountInfo<'info>, bool)]) -> Result<()> {
+        invoke_venue(self.program, self.common.vault.key, self.common.signer_seeds, data, metas)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn modify_liquidity(
+        &self,
+        discriminator: [u8; 8],
+        position: &AccountInfo<'info>,
+        position_token_account: &AccountInfo<'info>,
+        tick_array_lower: &AccountInfo<'info>,
+        tick_array_upper: &AccountInfo<'info>,
+        liquidity: u128,
+        token_a: u64,
+        token_b: u64,
+    ) -> Result<()> {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(&liquidity.to_le_bytes());
+        data.extend_from_slice(&token_a.to_le_bytes());
+        data.extend_from_slice(&token_b.to_le_bytes());
+        self.invoke(
+            data,
+            &[
+                (self.whirlpool, true),
+                (&self.common.token_program, false),
+                (&self.common.vault, false),
+                (position, true),
+                (position_token_account, false),
+                (self.owner_account_a(), true),
+                (self.owner_account_b(), true),
+                (self.token_vault_a, true),
+                (self.token_vault_b, true),
+                (tick_array_lower, true),
+                (tick_array_upper, true),
+            ],
+        )
+    }
+
+    fn balances(&self) -> Result<(u64, u64)> {
+        Ok((
+            token::accessor::amount(&self.common.base_vault)?,
+            token::accessor::amount(&self.common.quote_vault)?,
+        ))
+    }
+
+    /// Runs `collect`, then accrues the protocol's cut of what it brought
+    /// into the vault.
+    fn accrue_collected(&self, vault: &mut Vault, collect: impl FnOnce() -> Result<()>) -> Result<()> {
+        let before = self.balances()?;
+        collect()?;
+        let after = self.balances()?;
+        fees::accrue_pool_fees(vault, after.0.saturating_sub(before.0), after.1.saturating_sub(before.1))?;
+        Ok(())
+    }
+
+    /// Collects a position's fees into the vault and accrues the protocol's
+    /// cut of them.
+    fn collect_fees(
+        &self,
+        vault: &mut Vault,
+        position: &AccountInfo<'info>,
+        position_token_account: &AccountInfo<'info>,
+    ) -> Result<()> {
+        self.accrue_collected(vault, || self.invoke_collect_fees(position, position_token_account))
+    }
+
+    fn invoke_collect_fees(
+        &self,
+        position: &AccountInfo<'info>,
+        position_token_account: &AccountInfo<'info>,
+    ) -> Result<()> {
+        self.invoke(
+            IX_COLLECT_FEES.to_vec(),
+            &[
+                (self.whirlpool, false),
+                (&self.common.vault, false),
+                (position, true),
+                (position_token_account, false),
+                (self.owner_account_a(), true),
+                (self.token_vault_a, true),
+                (self.owner_account_b(), true),
+                (self.token_vault_b, true),
+                (&self.common.token_program, false),
+            ],
+        )
+    }
+
+    /// Rewards paid in the base or quote mint, as `(reward index, reward
+    /// vault, vault token account)`, taking each reward vault from the
+    /// venue accounts. Rewards in other mints stay owed on the positions.
+    fn reward_accounts(&mut self, vault: &Vault) -> Result<Vec<(u8, &'a AccountInfo<'info>, bool)>> {
+        let mut rewards = Vec::new();
+        for index in 0..WP_NUM_REWARDS {
+            let (mint, reward_vault) = {
+                let data = self.whirlpool.try_borrow_data()?;
+                let offset = WP_REWARD_INFOS + index * WP_REWARD_INFO_LEN;
+                (read_pubkey(&data, offset), read_pubkey(&data, offset + 32))
+            };
+            if mint == vault.base_mint || mint == vault.quote_mint {
+                let account = next_account(&mut self.rest, Some(&reward_vault))?;
+                rewards.push((index as u8, account, mint == vault.base_mint));
+            }
+        }
+        Ok(rewards)
+    }
+}
+
+impl<'a, 'info> LiquidityVenue<'info> for WhirlpoolVenue<'a, 'info> {
+    fn withdraw(&mut self, vault: &mut Vault) -> Result<()> {
+        for band in vault.positions.iter().map(|p| p.band).collect::<Vec<_>>() {
+            self.withdraw_band(vault, band)?;
+        }
+        Ok(())
+    }
+
+    fn withdraw_band(&mut self, vault: &mut Vault, band: i64) -> Result<bool> {
+        let Some(index) = vault.positions.iter().position(|p| p.band == band) else {
+            return Ok(false);
+        };
+        let open = vault.positions[index];
+        let position = next_account(&mut self.rest, Some(&open.position))?;
+        let position_mint = next_account(&mut self.rest, Some(&open.position_mint))?;
+        let position_token_account = next_account(&mut self.rest, None)?;
+        let tick_array_lower = next_account(&mut self.rest, None)?;
+        let tick_array_upper = next_account(&mut self.rest, None)?;
+        let receiver = next_account(&mut self.rest, Some(&open.funder))?;
+
+        let liquidity = read_u128(&position.try_borrow_data()?, POS_LIQUIDITY);
+        if liquidity > 0 {
+            self.modify_liquidity(
+                IX_DECREASE_LIQUIDITY,
+                position,
+                position_token_account,
+                tick_array_lower,
+                tick_array_upper,
+                liquidity,
+                0,
+                0,
+            )?;
+        }
+        self.collect_fees(vault, position, position_token_account)?;
+        self.invoke(
+            IX_CLOSE_POSITION.to_vec(),
+            &[
+                (&self.common.vault, false),
+                (receiver, true),
+                (position, true),
+                (position_mint, true),
+                (position_token_account, true),
+                (&self.common.token_program, false),
+            ],
+        )?;
+
+        vault.positions.remove(index);
+        Ok(false)
+    }
+
+    fn deploy(&mut self, vault: &mut Vault, band: i64, base_amount: u64, quote_amount: u64) -> Result<()> {
+        let funder = next_account(&mut self.rest, None)?;
+        let position = next_account(&mut self.rest, None)?;
+        let position_mint = next_account(&mut self.rest, None)?;
+        let position_token_account = next_account(&mut self.rest, None)?;
+        let tick_array_lower = next_account(&mut self.rest, None)?;
+        let tick_array_upper = next_account(&mut self.rest, None)?;
+        let system_program = next_account(&mut self.rest, None)?;
+        let rent = next_account(&mut self.rest, None)?;
+        let associated_token_program = next_account(&mut self.rest, None)?;
+
+        let (tick_lower, tick_upper) = self.tick_range(vault, band)?;
+        let (amount_a, amount_b) = if self.base_is_a {
+            (base_amount, quote_amount)
+        } else {
+            (quote_amount, base_amount)
+        };
+        let liquidity = math::liquidity_for_amounts(
+            self.sqrt_price,
+            math::sqrt_price_at_tick(tick_lower),
+            math::sqrt_price_at_tick(tick_upper),
+            amount_a,
+            amount_b,
+        )
+        .ok_or(VaultError::MathOverflow)?;
+        let liquidity = liquidity - liquidity * LIQUIDITY_HEADROOM_BPS / 10_000;
+        if liquidity == 0 {
+            return Ok(());
+        }
+
+        let (_, position_bump) = Pubkey::find_program_address(
+            &[b"position", position_mint.key.as_ref()],
+            self.program.key,
+        );
+        let mut data = IX_OPEN_POSITION.to_vec();
+        data.push(position_bump);
+        data.extend_from_slice(&tick_lower.to_le_bytes());
+        data.extend_from_slice(&tick_upper.to_le_bytes());
+        self.invoke(
+            data,
+            &[
+                (funder, true),
+                (&self.common.vault, false),
+                (position, true),
+                (position_mint, true),
+                (position_token_account, true),
+                (self.whirlpool, false),
+                (&self.common.token_program, false),
+                (system_program, false),
+                (rent, false),
+                (associated_token_program, false),
+            ],
+        )?;
+        self.modify_liquidity(
+            IX_INCREASE_LIQUIDITY,
+            position,
+            position_token_account,
+            tick_array_lower,
+            tick_array_upper,
+            liquidity,
+            amount_a,
+            amount_b,
+        )?;
+
+        vault.positions.push(LpPosition {
+            band,
+            position: *position.key,
+            position_mint: *position_mint.key,
+            funder: *funder.key,
+            tick_lower,
+            tick_upper,
+            liquidity,
+        });
+        Ok(())
+    }
+
+    fn reduce(&mut self, vault: &mut Vault, shares: u64, supply: u64) -> Result<bool> {
+        for i in 0..vault.positions.len() {
+            let open = vault.positions[i];
+            let position = next_account(&mut self.rest, Some(&open.position))?;
+            let position_token_account = next_account(&mut self.rest, None)?;
+            let tick_array_lower = next_account(&mut self.rest, None)?;
+            let tick_array_upper = next_account(&mut self.rest, None)?;
+
+            let liquidity = math::mul_div(open.liquidity, shares as u128, supply as u128)
+                .ok_or(VaultError::MathOverflow)?;
+            if liquidity > 0 {
+                self.modify_liquidity(
+                    IX_DECREASE_LIQUIDITY,
+                    position,
+                    position_token_account,
+                    tick_array_lower,
+                    tick_array_upper,
+                    liquidity,
+                    0,
+                    0,
+                )?;
+                vault.positions[i].liquidity -= liquidity;
+            }
+            // fees are owed to every holder, so they come back in full
+            self.collect_fees(vault, position, position_token_account)?;
+        }
+        Ok(false)
+    }
+
+    fn harvest(&mut self, vault: &mut Vault, band: i64) -> Result<HarvestResult> {
+        let rewards = self.reward_accounts(vault)?;
+        let accrued = (vault.accrued_fee_base, vault.accrued_fee_quote);
+        let before = self.balances()?;
+        let mut active = None;
+        for i in 0..vault.positions.len() {
+            let open = vault.positions[i];
+            let position = next_account(&mut self.rest, Some(&open.position))?;
+            let position_token_account = next_account(&mut self.rest, None)?;
+            let tick_array_lower = next_account(&mut self.rest, None)?;
+            let tick_array_upper = next_account(&mut self.rest, None)?;
+
+            // fees and rewards owed are only brought up to date when the position is touched
+            self.invoke(
+                IX_UPDATE_FEES_AND_REWARDS.to_vec(),
+                &[
+                    (self.whirlpool, true),
+                    (position, true),
+                    (tick_array_lower, false),
+                    (tick_array_upper, false),
+                ],
+            )?;
+            self.accrue_collected(vault, || {
+                self.invoke_collect_fees(position, position_token_account)?;
+                for &(index, reward_vault, is_base) in &rewards {
+                    let owner_account = if is_base { &self.common.base_vault } else { &self.common.quote_vault };
+                    let mut data = IX_COLLECT_REWARD.to_vec();
+                    data.push(index);
+                    self.invoke(
+                        data,
+                        &[
+                            (self.whirlpool, false),
+                            (&self.common.vault, false),
+                            (position, true),
+                            (position_token_account, false),
+                            (owner_account, true),
+                            (reward_vault, true),
+                            (&self.common.token_program, false),
+                        ],
+                    )?;
+                }
+                Ok(())
+            })?;
+            if open.band == band {
+                active = Some((i, position, position_token_account, tick_array_lower, tick_array_upper));
+            }
+        }
+        let after = self.balances()?;
+        let mut harvest = HarvestResult {
+            base: after.0.saturating_sub(before.0),
+            quote: after.1.saturating_sub(before.1),
+            protocol_base: vault.accrued_fee_base - accrued.0,
+            protocol_quote: vault.accrued_fee_quote - accrued.1,
+            ..HarvestResult::default()
+        };
+
+        // the holders' part goes back into the active band; without a position there it waits for the next rebalance
+        let Some((i, position, position_token_account, tick_array_lower, tick_array_upper)) = active else {
+            return Ok(harvest);
+        };
+        let (deployable_base, deployable_quote) = self.common.deployable_balances(vault)?;
+        let base_amount = (harvest.base - harvest.protocol_base).min(deployable_base);
+        let quote_amount = (harvest.quote - harvest.protocol_quote).min(deployable_quote);
+        let open = vault.positions[i];
+        let (amount_a, amount_b) = if self.base_is_a {
+            (base_amount, quote_amount)
+        } else {
+            (quote_amount, base_amount)
+        };
+        let liquidity = math::liquidity_for_amounts(
+            self.sqrt_price,
+            math::sqrt_price_at_tick(open.tick_lower),
+            math::sqrt_price_at_tick(open.tick_upper),
+            amount_a,
+            amount_b,
+        )
+        .ok_or(VaultError::MathOverflow)?;
+        let liquidity = liquidity - liquidity * LIQUIDITY_HEADROOM_BPS / 10_000;
+        if liquidity == 0 {
+            return Ok(harvest);
+        }
+        self.modify_liquidity(
+            IX_INCREASE_LIQUIDITY,
+            position,
+            position_token_account,
+            tick_array_lower,
+            tick_array_upper,
+            liquidity,
+            amount_a,
+            amount_b,
+        )?;
+        vault.positions[i].liquidity += liquidity;
+        let compounded = self.balances()?;
+        harvest.compounded_base = after.0.saturating_sub(compounded.0);
+        harvest.compounded_quote = after.1.saturating_sub(compounded.1);
+        Ok(harvest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIQUIDITY: u128 = 1_000_000_000;
+
+    /// A vault holding one position over ticks -1000..1000, roughly
+    /// 0.905..1.105 quote per base at equal decimals.
+    fn vault() -> Vault {
+        let mut vault = crate::tests::largest_vault();
+        vault.base_decimals = 6;
+        vault.quote_decimals = 6;
+        vault.fee_bps = 0;
+        vault.positions.truncate(1);
+        vault.positions[0].tick_lower = -1_000;
+        vault.positions[0].tick_upper = 1_000;
+        vault
+    }
+
+    fn sqrt_price(price: u64) -> u128 {
+        math::sqrt_price_x64(math::price_to_x64(price, 6, 6).unwrap())
+    }
+
+    /// Splits the vault's position against a whirlpool at `pool_sqrt_price`.
+    fn amounts(vault: &Vault, base_is_a: bool, pool_sqrt_price: u128, price: Option<u64>) -> (u64, u64) {
+        let mut pool = vec![0u8; WP_LEN];
+        pool[..8].copy_from_slice(&WHIRLPOOL_DISCRIMINATOR);
+        let (mint_a, mint_b) =
+            if base_is_a { (vault.base_mint, vault.quote_mint) } else { (vault.quote_mint, vault.base_mint) };
+        pool[WP_TOKEN_MINT_A..WP_TOKEN_MINT_A + 32].copy_from_slice(mint_a.as_ref());
+        pool[WP_TOKEN_MINT_B..WP_TOKEN_MINT_B + 32].copy_from_slice(mint_b.as_ref());
+        pool[WP_SQRT_PRICE..WP_SQRT_PRICE + 16].copy_from_slice(&pool_sqrt_price.to_le_bytes());
+        let mut position = vec![0u8; POS_LEN];
+        position[POS_LIQUIDITY..POS_LIQUIDITY + 16].copy_from_slice(&LIQUIDITY.to_le_bytes());
+        let (mut pool_lamports, mut position_lamports) = (0, 0);
+        let accounts = [
+            AccountInfo::new(
+                &vault.venue_market,
+                false,
+                false,
+                &mut pool_lamports,
+                &mut pool,
+                &vault.venue_program,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &vault.positions[0].position,
+                false,
+                false,
+                &mut position_lamports,
+                &mut position,
+                &vault.venue_program,
+                false,
+                0,
+            ),
+        ];
+        let (amounts, used) = position_amounts(vault, &accounts, price).unwrap();
+        assert_eq!(used, 2);
+        amounts
+    }
+
+    #[test]
+    fn position_below_range_is_all_base() {
+        let vault = vault();
+        let (base, quote) = amounts(&vault, true, sqrt_price(500_000), None);
+        assert!(base > 0);
+        assert_eq!(quote, 0);
+        assert_eq!(amounts(&vault, true, sqrt_price(1_000_000), Some(500_000)), (base, quote));
+    }
+
+    #[test]
+    fn position_inside_range_holds_both_sides() {
+        let vault = vault();
+        let (base, quote) = amounts(&vault, true, sqrt_price(1_000_000), None);
+        assert!(base > 0 && quote > 0);
+        // symmetric range at its midpoint
+        assert!(base.abs_diff(quote) <= 1, "{base} {quote}");
+    }
+
+    #[test]
+    fn position_above_range_is_all_quote() {
+        let vault = vault();
+        let (base, quote) = amounts(&vault, true, sqrt_price(2_000_000), None);
+        assert_eq!(base, 0);
+        assert!(quote > 0);
+        assert_eq!(amounts(&vault, true, sqrt_price(1_000_000), Some(2_000_000)), (base, quote));
+    }
+
+    #[test]
+    fn oracle_split_ignores_the_pool_price() {
+        let vault = vault();
+        let fair = amounts(&vault, true, sqrt_price(1_000_000), None);
+        // pool pushed above the range, e.g. by a swap earlier in the transaction
+        assert_eq!(amounts(&vault, true, sqrt_price(4_000_000), Some(1_000_000)), fair);
+        assert_ne!(amounts(&vault, true, sqrt_price(4_000_000), None), fair);
+    }
+
+    #[test]
+    fn oracle_split_follows_token_order() {
+        let vault = vault();
+        // base is token B: a cheap base is above the range in A/B terms, all B
+        let (base, quote) = amounts(&vault, false, sqrt_price(1_000_000), Some(500_000));
+        assert!(base > 0);
+        assert_eq!(quote, 0);
+        let (base, quote) = amounts(&vault, false, sqrt_price(1_000_000), Some(2_000_000));
+        assert_eq!(base, 0);
+        assert!(quote > 0);
+    }
+}
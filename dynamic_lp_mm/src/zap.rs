@@ -0,0 +1,91 @@
+use anchor_lang::prelude::*;
+
+use crate::math;
+use crate::nav::Nav;
+
+/// Which vault token a single-sided deposit or withdrawal is made in.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenSide {
+    Base,
+    Quote,
+}
+
+/// Value credited for a single-sided deposit of `amount` on `side` into a
+/// vault holding `nav`. The part of the deposit that would have to be
+/// swapped to match the vault's mix is valued at `price` less
+/// `zap_fee_bps`; the rest at `price`. Returns the deposit value in quote
+/// atoms.
+pub fn zap_in_value(
+    nav: &Nav,
+    side: TokenSide,
+    amount: u64,
+    price: u64,
+    base_decimals: u8,
+    quote_decimals: u8,
+    zap_fee_bps: u16,
+) -> Option<u64> {
+    let base_value = math::base_value_in_quote(nav.base, price, base_decimals, quote_decimals)?;
+    let total = base_value as u128 + nav.quote as u128;
+    let (value, other_side) = match side {
+        TokenSide::Base => (math::base_value_in_quote(amount, price, base_decimals, quote_decimals)?, nav.quote),
+        TokenSide::Quote => (amount, base_value),
+    };
+    if total == 0 {
+        return Some(value);
+    }
+    let swapped = math::mul_div(value as u128, other_side as u128, total)?;
+    let fee = swapped * zap_fee_bps as u128 / 10_000;
+    u64::try_from(value as u128 - fee).ok()
+}
+
+/// What a holder's `claim` of base and quote pays out on `side`: the other
+/// token is sold to the vault at `price` less `zap_fee_bps`.
+pub fn zap_out_amount(
+    claim: (u64, u64),
+    side: TokenSide,
+    price: u64,
+    base_decimals: u8,
+    quote_decimals: u8,
+    zap_fee_bps: u16,
+) -> Option<u64> {
+    let (kept, converted) = match side {
+        TokenSide::Base => (claim.0, math::quote_value_in_base(claim.1, price, base_decimals, quote_decimals)?),
+        TokenSide::Quote => (claim.1, math::base_value_in_quote(claim.0, price, base_decimals, quote_decimals)?),
+    };
+    let net = math::mul_div(converted as u128, 10_000 - zap_fee_bps as u128, 10_000)?;
+    kept.checked_add(u64::try_from(net).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 base = 2 quote, base has 9 decimals and quote 6
+    const PRICE: u64 = 2 * math::PRICE_SCALE;
+
+    #[test]
+    fn zap_in_charges_only_the_swapped_part() {
+        // vault worth 1_000 quote of base and 3_000 quote
+        let nav = Nav { base: 500_000, quote: 3_000 };
+        // a quote deposit swaps the base share of value, 25%
+        assert_eq!(zap_in_value(&nav, TokenSide::Quote, 1_000, PRICE, 3, 0, 100), Some(998));
+        // a base deposit swaps the quote share, 75%: 2_000 quote of value less 15
+        assert_eq!(zap_in_value(&nav, TokenSide::Base, 1_000_000, PRICE, 3, 0, 100), Some(1_985));
+        assert_eq!(zap_in_value(&nav, TokenSide::Quote, 1_000, PRICE, 3, 0, 0), Some(1_000));
+    }
+
+    #[test]
+    fn zap_in_to_empty_vault_is_free() {
+        assert_eq!(zap_in_value(&Nav::default(), TokenSide::Quote, 1_000, PRICE, 6, 6, 500), Some(1_000));
+    }
+
+    #[test]
+    fn zap_out_sells_the_other_side() {
+        // 100 base + 300 quote at equal decimals
+        assert_eq!(zap_out_amount((100, 300), TokenSide::Quote, PRICE, 6, 6, 100), Some(498));
+        assert_eq!(zap_out_amount((100, 300), TokenSide::Base, PRICE, 6, 6, 100), Some(248));
+        assert_eq!(zap_out_amount((100, 300), TokenSide::Base, PRICE, 6, 6, 0), Some(250));
+        // 1 base atom at 9 decimals against quote at 6
+        assert_eq!(math::quote_value_in_base(2, PRICE, 9, 6), Some(1_000));
+    }
+}
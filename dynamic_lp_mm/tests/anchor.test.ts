@@ -1,5 +1,6 @@
-// No imports needed: web3, pg, anchor are all globally available
+// web3, pg, anchor are globally available
 //Basic test file
+import * as spl from "@solana/spl-token";
 
 describe("Dynamic LP Vault Full Test", () => {
   let vaultPda: web3.PublicKey;
@@ -11,6 +12,32 @@ describe("Dynamic LP Vault Full Test", () => {
   const baseMint = new web3.Keypair();
   const quoteMint = new web3.Keypair();
   const treasury = new web3.Keypair();
+  const referrer = new web3.Keypair();
+
+  // Pyth SOL/USD sponsored price feed, kept fresh by Pyth on devnet
+  const pythFeedId = Buffer.from("ef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d", "hex");
+  const pythPriceUpdate = new web3.PublicKey("7UVimffxr9ow1uXYxsr4LHAcV58mLzhmwaeKvJ1pjLiE");
+  let oracleConfigPda: web3.PublicKey;
+  let userBaseAta: web3.PublicKey;
+  let userQuoteAta: web3.PublicKey;
+  let userShareAta: web3.PublicKey;
+  let treasuryShareAta: web3.PublicKey;
+
+  const referralPda = () =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("referral"), vaultPda.toBuffer(), pg.wallet.publicKey.toBuffer()],
+      pg.program.programId
+    )[0];
+
+  it("create mints", async () => {
+    const payer = pg.wallet.keypair;
+    await spl.createMint(pg.connection, payer, payer.publicKey, null, 9, baseMint);
+    await spl.createMint(pg.connection, payer, payer.publicKey, null, 6, quoteMint);
+    userBaseAta = await spl.createAssociatedTokenAccount(pg.connection, payer, baseMint.publicKey, payer.publicKey);
+    userQuoteAta = await spl.createAssociatedTokenAccount(pg.connection, payer, quoteMint.publicKey, payer.publicKey);
+    await spl.mintTo(pg.connection, payer, baseMint.publicKey, userBaseAta, payer, 10_000_000_000);
+    await spl.mintTo(pg.connection, payer, quoteMint.publicKey, userQuoteAta, payer, 10_000_000_000);
+  });
 
   it("initialize vault", async () => {
     [vaultPda, vaultBump] = await web3.PublicKey.findProgramAddressSync(
@@ -44,59 +71,110 @@ describe("Dynamic LP Vault Full Test", () => {
         shareMint: shareMintPda,
         baseVault: baseVaultPda,
         quoteVault: quoteVaultPda,
-        tokenProgram: web3.SystemProgram.programId, 
+        tokenProgram: spl.TOKEN_PROGRAM_ID,
         systemProgram: web3.SystemProgram.programId,
         rent: web3.SYSVAR_RENT_PUBKEY,
       })
       .rpc();
 
+    const payer = pg.wallet.keypair;
+    userShareAta = await spl.createAssociatedTokenAccount(pg.connection, payer, shareMintPda, payer.publicKey);
+    treasuryShareAta = await spl.createAssociatedTokenAccount(pg.connection, payer, shareMintPda, treasury.publicKey);
+
     console.log("✅ Vault initialized");
   });
 
-  it("fake deposit", async () => {
-    const depositAmount = new anchor.BN(1000000); // 1 token
+  it("init oracle config", async () => {
+    [oracleConfigPda] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("oracle_config"), vaultPda.toBuffer()],
+      pg.program.programId
+    );
 
-    const fakeUserBaseAta = new web3.Keypair();
-    const fakeUserQuoteAta = new web3.Keypair();
-    const fakeUserShareAta = new web3.Keypair();
+    await pg.program.methods
+      .setOracle(Array.from(pythFeedId), new anchor.BN(3600), 500)
+      .accounts({ vault: vaultPda, authority: pg.wallet.publicKey })
+      .rpc();
 
     const tx = await pg.program.methods
-      .deposit(depositAmount, depositAmount)
+      .initOracleConfig([{ kind: { pyth: {} }, id: Array.from(pythFeedId) }], 500)
+      .accounts({
+        vault: vaultPda,
+        authority: pg.wallet.publicKey,
+        oracleConfig: oracleConfigPda,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    console.log("✅ Init Oracle Config Tx", tx);
+  });
+
+  it("deposit", async () => {
+    const baseAmount = new anchor.BN(1_000_000_000); // 1 base token
+    const quoteAmount = new anchor.BN(1_000_000); // 1 quote token
+
+    const tx = await pg.program.methods
+      .deposit(baseAmount, quoteAmount, new anchor.BN(1), null, referrer.publicKey)
       .accounts({
         vault: vaultPda,
         shareMint: shareMintPda,
         baseVault: baseVaultPda,
         quoteVault: quoteVaultPda,
         user: pg.wallet.publicKey,
-        userBaseAta: fakeUserBaseAta.publicKey,
-        userQuoteAta: fakeUserQuoteAta.publicKey,
-        userShareAta: fakeUserShareAta.publicKey,
-        tokenProgram: web3.SystemProgram.programId, 
+        userBaseAta,
+        userQuoteAta,
+        userShareAta,
+        treasuryShareAta,
+        oracleConfig: oracleConfigPda,
+        referral: referralPda(),
+        tokenProgram: spl.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
       })
-      .rpc();
+      .remainingAccounts([{ pubkey: pythPriceUpdate, isSigner: false, isWritable: false }])
+      .rpc({ commitment: "confirmed" });
 
-    console.log("✅ Deposit Tx", tx);
+    const shares = new anchor.BN(
+      (await pg.connection.getTokenAccountBalance(userShareAta, "confirmed")).value.amount
+    );
+    const nav = await pg.program.methods
+      .getNav()
+      .accounts({
+        vault: vaultPda,
+        shareMint: shareMintPda,
+        baseVault: baseVaultPda,
+        quoteVault: quoteVaultPda,
+        oracleConfig: oracleConfigPda,
+      })
+      .remainingAccounts([{ pubkey: pythPriceUpdate, isSigner: false, isWritable: false }])
+      .view();
+
+    // the first deposit mints one share atom per quote atom of value
+    assert(nav.shareSupply.eq(shares), "all shares went to the depositor");
+    assert(nav.base.eq(baseAmount) && nav.quote.eq(quoteAmount), "both sides were taken in full");
+    const expected = baseAmount.mul(nav.price).div(new anchor.BN(1_000_000_000)).add(quoteAmount);
+    // the oracle may have ticked between the two transactions
+    assert(shares.sub(expected).abs().lte(expected.divn(100)), `minted ${shares}, expected ~${expected}`);
+
+    console.log("✅ Deposit Tx", tx, "shares", shares.toString());
   });
 
-  it("fake withdraw", async () => {
+  it("withdraw", async () => {
     const withdrawAmount = new anchor.BN(1000000); // 1 share
 
-    const fakeUserBaseAta = new web3.Keypair();
-    const fakeUserQuoteAta = new web3.Keypair();
-    const fakeUserShareAta = new web3.Keypair();
-
     const tx = await pg.program.methods
-      .withdraw(withdrawAmount)
+      .withdraw(withdrawAmount, new anchor.BN(0), new anchor.BN(0), null)
       .accounts({
         vault: vaultPda,
         shareMint: shareMintPda,
         baseVault: baseVaultPda,
         quoteVault: quoteVaultPda,
         user: pg.wallet.publicKey,
-        userBaseAta: fakeUserBaseAta.publicKey,
-        userQuoteAta: fakeUserQuoteAta.publicKey,
-        userShareAta: fakeUserShareAta.publicKey,
-        tokenProgram: web3.SystemProgram.programId,
+        userBaseAta,
+        userQuoteAta,
+        userShareAta,
+        treasuryShareAta,
+        oracleConfig: oracleConfigPda,
+        referral: referralPda(),
+        tokenProgram: spl.TOKEN_PROGRAM_ID,
       })
       .rpc();
 
@@ -105,14 +183,19 @@ describe("Dynamic LP Vault Full Test", () => {
 
   it("rebalance vault", async () => {
     const fakePrice = new anchor.BN(10000);
+    const fakeTreasuryShareAta = new web3.Keypair();
 
     const tx = await pg.program.methods
-      .rebalance(fakePrice)
+      .rebalance(fakePrice, null)
       .accounts({
         vault: vaultPda,
         authority: pg.wallet.publicKey,
         baseVault: baseVaultPda,
         quoteVault: quoteVaultPda,
+        shareMint: shareMintPda,
+        treasuryShareAta: fakeTreasuryShareAta.publicKey,
+        referralShareVault: null,
+        priceHistory: null,
         tokenProgram: web3.SystemProgram.programId,
         clock: web3.SYSVAR_CLOCK_PUBKEY,
       })
@@ -121,6 +204,25 @@ describe("Dynamic LP Vault Full Test", () => {
     console.log("✅ Rebalance Tx", tx);
   });
 
+  it("init price history", async () => {
+    const [priceHistoryPda] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("price_history"), vaultPda.toBuffer()],
+      pg.program.programId
+    );
+
+    const tx = await pg.program.methods
+      .initPriceHistory(new anchor.BN(30))
+      .accounts({
+        vault: vaultPda,
+        authority: pg.wallet.publicKey,
+        priceHistory: priceHistoryPda,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    console.log("✅ Init Price History Tx", tx);
+  });
+
   it("sweep fees", async () => {
     const fakeTreasuryBaseAta = new web3.Keypair();
     const fakeTreasuryQuoteAta = new web3.Keypair();
@@ -0,0 +1,340 @@
+//! Program-test harness shared by the venue round trips. The vault program
+//! runs natively; venue programs are loaded from `tests/fixtures/<name>.so`
+//! (see `tests/fixtures/fetch.sh`), and tests are skipped without them.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::{system_program, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::spl_token;
+use dynamic_lp_mm::oracle::PYTH_RECEIVER_PROGRAM_ID;
+use dynamic_lp_mm::{accounts, instruction, Vault};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::Account;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+
+pub const PYTH_FEED: [u8; 32] = [7; 32];
+
+fn process(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    // the native entry wants account infos that live as long as their data
+    let accounts: &[AccountInfo] = Box::leak(Box::new(accounts.to_vec()));
+    let accounts = unsafe { std::mem::transmute::<&[AccountInfo], &[AccountInfo]>(accounts) };
+    dynamic_lp_mm::entry(program_id, accounts, data)
+}
+
+/// Anchor instruction discriminator of a venue program instruction.
+pub fn sighash(name: &str) -> [u8; 8] {
+    hash(format!("global:{name}").as_bytes()).to_bytes()[..8].try_into().unwrap()
+}
+
+pub struct Env {
+    pub ctx:   ProgramTestContext,
+    pub payer: Keypair,
+}
+
+/// Starts a bank with the vault program and the venue `programs`, or
+/// `None` when a venue fixture is missing.
+pub async fn start(programs: &[(&str, Pubkey)]) -> Option<Env> {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    if let Some((name, _)) = programs.iter().find(|(name, _)| !fixtures.join(format!("{name}.so")).exists()) {
+        eprintln!("skipping: tests/fixtures/{name}.so missing, run tests/fixtures/fetch.sh");
+        return None;
+    }
+    std::env::set_var("SBF_OUT_DIR", &fixtures);
+    let mut test = ProgramTest::new("dynamic_lp_mm", dynamic_lp_mm::ID, processor!(process));
+    for (name, id) in programs {
+        test.add_program(name, *id, None);
+    }
+    let ctx = test.start_with_context().await;
+    let payer = ctx.payer.insecure_clone();
+    Some(Env { ctx, payer })
+}
+
+impl Env {
+    pub async fn send(&mut self, ixs: &[Instruction], signers: &[&Keypair]) -> std::result::Result<(), BanksClientError> {
+        let mut all = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
+        all.extend_from_slice(ixs);
+        let mut keys = vec![&self.payer];
+        keys.extend_from_slice(signers);
+        let blockhash = self.ctx.banks_client.get_latest_blockhash().await?;
+        let tx = Transaction::new_signed_with_payer(&all, Some(&self.payer.pubkey()), &keys, blockhash);
+        self.ctx.banks_client.process_transaction(tx).await
+    }
+
+    pub async fn account(&mut self, key: Pubkey) -> Option<Account> {
+        self.ctx.banks_client.get_account(key).await.unwrap()
+    }
+
+    pub async fn now(&mut self) -> i64 {
+        self.ctx.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    /// Creates a program-owned account of `space` zeroed bytes.
+    pub async fn create_account(&mut self, account: &Keypair, space: usize, owner: &Pubkey) {
+        let lamports = Rent::default().minimum_balance(space);
+        let ix = solana_sdk::system_instruction::create_account(
+            &self.payer.pubkey(),
+            &account.pubkey(),
+            lamports,
+            space as u64,
+            owner,
+        );
+        self.send(&[ix], &[account]).await.unwrap();
+    }
+
+    pub async fn create_mint(&mut self, mint: &Keypair, decimals: u8) {
+        self.create_account(mint, spl_token::state::Mint::LEN, &spl_token::ID).await;
+        let ix = spl_token::instruction::initialize_mint2(&spl_token::ID, &mint.pubkey(), &self.payer.pubkey(), None, decimals)
+            .unwrap();
+        self.send(&[ix], &[]).await.unwrap();
+    }
+
+    pub async fn create_token_account(&mut self, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+        let account = Keypair::new();
+        self.create_account(&account, spl_token::state::Account::LEN, &spl_token::ID).await;
+        let ix = spl_token::instruction::initialize_account3(&spl_token::ID, &account.pubkey(), mint, owner).unwrap();
+        self.send(&[ix], &[]).await.unwrap();
+        account.pubkey()
+    }
+
+    pub async fn mint_to(&mut self, mint: &Pubkey, to: &Pubkey, amount: u64) {
+        let ix = spl_token::instruction::mint_to(&spl_token::ID, mint, to, &self.payer.pubkey(), &[], amount).unwrap();
+        self.send(&[ix], &[]).await.unwrap();
+    }
+
+    pub async fn token_balance(&mut self, account: Pubkey) -> u64 {
+        let data = self.account(account).await.unwrap().data;
+        spl_token::state::Account::unpack(&data).unwrap().amount
+    }
+
+    /// Writes a fully verified Pyth `PriceUpdateV2` for `PYTH_FEED`,
+    /// published now.
+    pub async fn set_pyth_price(&mut self, key: Pubkey, price: i64, exponent: i32) {
+        let now = self.now().await;
+        let mut data = vec![34, 241, 35, 99, 157, 126, 244, 205];
+        data.extend_from_slice(&[0; 32]); // write_authority
+        data.push(1); // verification_level: Full
+        data.extend_from_slice(&PYTH_FEED);
+        data.extend_from_slice(&price.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes()); // conf
+        data.extend_from_slice(&exponent.to_le_bytes());
+        data.extend_from_slice(&now.to_le_bytes()); // publish_time
+        data.extend_from_slice(&now.to_le_bytes()); // prev_publish_time
+        data.extend_from_slice(&price.to_le_bytes()); // ema_price
+        data.extend_from_slice(&0u64.to_le_bytes()); // ema_conf
+        data.extend_from_slice(&0u64.to_le_bytes()); // posted_slot
+        let account = Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: PYTH_RECEIVER_PROGRAM_ID,
+            executable: false,
+            rent_epoch: 0,
+        };
+        self.ctx.set_account(&key, &account.into());
+    }
+}
+
+/// A vault over two fresh mints, with a funded depositor.
+pub struct TestVault {
+    pub vault:       Pubkey,
+    pub base_mint:   Pubkey,
+    pub quote_mint:  Pubkey,
+    pub share_mint:  Pubkey,
+    pub base_vault:  Pubkey,
+    pub quote_vault: Pubkey,
+    pub treasury_share_ata: Pubkey,
+    pub user_base:   Pubkey,
+    pub user_quote:  Pubkey,
+    pub user_shares: Pubkey,
+    pub pyth:        Pubkey,
+}
+
+fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &dynamic_lp_mm::ID).0
+}
+
+impl TestVault {
+    /// Creates the mints (base sorting before quote, so base is a
+    /// whirlpool's token A), the vault and the depositor's accounts, and
+    /// links a Pyth price of 1 quote per base.
+    pub async fn new(env: &mut Env, band_size_bp: u16) -> Self {
+        let (mut a, mut b) = (Keypair::new(), Keypair::new());
+        if a.pubkey() > b.pubkey() {
+            std::mem::swap(&mut a, &mut b);
+        }
+        env.create_mint(&a, 6).await;
+        env.create_mint(&b, 6).await;
+        let (base_mint, quote_mint) = (a.pubkey(), b.pubkey());
+        let vault = pda(&[b"vault", base_mint.as_ref(), quote_mint.as_ref()]);
+        let share_mint = pda(&[b"share_mint", vault.as_ref()]);
+        let base_vault = pda(&[b"base_vault", vault.as_ref()]);
+        let quote_vault = pda(&[b"quote_vault", vault.as_ref()]);
+        let treasury = Pubkey::new_unique();
+        let ix = Instruction {
+            program_id: dynamic_lp_mm::ID,
+            accounts:   accounts::Initialize {
+                vault,
+                authority: env.payer.pubkey(),
+                treasury,
+                base_mint,
+                quote_mint,
+                share_mint,
+                base_vault,
+                quote_vault,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+                rent: solana_sdk::sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data:       instruction::Initialize { fee_bps: 1_000, band_size_bp }.data(),
+        };
+        env.send(&[ix], &[]).await.unwrap();
+
+        let payer = env.payer.pubkey();
+        let user_base = env.create_token_account(&base_mint, &payer).await;
+        let user_quote = env.create_token_account(&quote_mint, &payer).await;
+        let user_shares = env.create_token_account(&share_mint, &payer).await;
+        let treasury_share_ata = env.create_token_account(&share_mint, &treasury).await;
+        env.mint_to(&base_mint, &user_base, 1_000_000_000_000).await;
+        env.mint_to(&quote_mint, &user_quote, 1_000_000_000_000).await;
+
+        let pyth = Pubkey::new_unique();
+        env.set_pyth_price(pyth, 100_000_000, -8).await;
+        let tv = Self {
+            vault,
+            base_mint,
+            quote_mint,
+            share_mint,
+            base_vault,
+            quote_vault,
+            treasury_share_ata,
+            user_base,
+            user_quote,
+            user_shares,
+            pyth,
+        };
+        tv.admin(env, instruction::SetOracle { feed_id: PYTH_FEED, max_age_sec: 60, max_conf_bps: 100 }.data())
+            .await;
+        tv
+    }
+
+    /// Sends a vault instruction taking the `UpdateFee` accounts.
+    pub async fn admin(&self, env: &mut Env, data: Vec<u8>) {
+        let ix = Instruction {
+            program_id: dynamic_lp_mm::ID,
+            accounts:   accounts::UpdateFee { vault: self.vault, authority: env.payer.pubkey() }.to_account_metas(None),
+            data,
+        };
+        env.send(&[ix], &[]).await.unwrap();
+    }
+
+    pub async fn state(&self, env: &mut Env) -> Vault {
+        let data = env.account(self.vault).await.unwrap().data;
+        Vault::try_deserialize(&mut &data[..]).unwrap()
+    }
+
+    fn referral(&self, user: &Pubkey) -> Pubkey {
+        pda(&[b"referral", self.vault.as_ref(), user.as_ref()])
+    }
+
+    /// Deposits both tokens; `nav` are the venue's NAV accounts.
+    pub async fn deposit(&self, env: &mut Env, base: u64, quote: u64, nav: &[AccountMeta]) {
+        let user = env.payer.pubkey();
+        let mut metas = accounts::Deposit {
+            vault: self.vault,
+            share_mint: self.share_mint,
+            base_vault: self.base_vault,
+            quote_vault: self.quote_vault,
+            user,
+            user_base_ata: self.user_base,
+            user_quote_ata: self.user_quote,
+            user_share_ata: self.user_shares,
+            treasury_share_ata: self.treasury_share_ata,
+            oracle_config: None,
+            referral: self.referral(&user),
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        metas.push(AccountMeta::new_readonly(self.pyth, false));
+        metas.extend_from_slice(nav);
+        let data = instruction::Deposit {
+            base_amount:    base,
+            quote_amount:   quote,
+            min_shares_out: 1,
+            deadline:       None,
+            referrer:       None,
+        }
+        .data();
+        env.send(&[Instruction { program_id: dynamic_lp_mm::ID, accounts: metas, data }], &[])
+            .await
+            .unwrap();
+    }
+
+    /// Rebalances to `price`; `remaining` are the NAV then venue accounts.
+    pub async fn rebalance(&self, env: &mut Env, price: u64, remaining: &[AccountMeta], signers: &[&Keypair]) {
+        let mut metas = accounts::Rebalance {
+            vault: self.vault,
+            authority: env.payer.pubkey(),
+            base_vault: self.base_vault,
+            quote_vault: self.quote_vault,
+            share_mint: self.share_mint,
+            treasury_share_ata: self.treasury_share_ata,
+            referral_share_vault: None,
+            price_history: None,
+            token_program: spl_token::ID,
+            clock: solana_sdk::sysvar::clock::ID,
+        }
+        .to_account_metas(None);
+        metas.extend_from_slice(remaining);
+        let data = instruction::Rebalance { current_price: price, swap_leg: None }.data();
+        env.send(&[Instruction { program_id: dynamic_lp_mm::ID, accounts: metas, data }], signers)
+            .await
+            .unwrap();
+    }
+
+    /// Burns `shares`; `remaining` are the NAV then venue accounts.
+    pub async fn withdraw(&self, env: &mut Env, shares: u64, remaining: &[AccountMeta]) {
+        let user = env.payer.pubkey();
+        let mut metas = accounts::Withdraw {
+            vault: self.vault,
+            share_mint: self.share_mint,
+            base_vault: self.base_vault,
+            quote_vault: self.quote_vault,
+            user,
+            user_base_ata: self.user_base,
+            user_quote_ata: self.user_quote,
+            user_share_ata: self.user_shares,
+            treasury_share_ata: self.treasury_share_ata,
+            oracle_config: None,
+            referral: self.referral(&user),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None);
+        metas.extend_from_slice(remaining);
+        let data = instruction::Withdraw {
+            share_amount:  shares,
+            min_base_out:  0,
+            min_quote_out: 0,
+            deadline:      None,
+        }
+        .data();
+        env.send(&[Instruction { program_id: dynamic_lp_mm::ID, accounts: metas, data }], &[])
+            .await
+            .unwrap();
+    }
+
+    /// The vault-owned token account of a position NFT.
+    pub fn position_token_account(&self, position_mint: &Pubkey) -> Pubkey {
+        get_associated_token_address(&self.vault, position_mint)
+    }
+}
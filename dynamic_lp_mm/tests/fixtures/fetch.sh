@@ -0,0 +1,6 @@
+#!/bin/sh
+# Dumps the venue programs the program-test round trips load from mainnet.
+set -e
+cd "$(dirname "$0")"
+solana program dump -u m whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc whirlpool.so
+solana program dump -u m opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb openbook_v2.so
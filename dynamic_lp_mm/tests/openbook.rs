@@ -0,0 +1,231 @@
+//! OpenBook v2 round trip: a rebalance places the quote ladder, a
+//! withdrawal cancels the holder's share of it, and `settle_funds` pulls
+//! free balances back into the vault.
+
+mod common;
+
+use anchor_lang::prelude::*;
+use anchor_lang::{system_program, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token::spl_token;
+use common::{sighash, start, Env, TestVault};
+use dynamic_lp_mm::venue::VenueKind;
+use dynamic_lp_mm::{accounts, instruction};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+const OPENBOOK_ID: Pubkey = pubkey!("opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb");
+// account sizes, including the discriminator
+const BOOK_SIDE_LEN: usize = 8 + 90_944;
+const EVENT_HEAP_LEN: usize = 8 + 91_280;
+// `OpenOrdersAccount.position` field offsets
+const OO_ASKS_BASE_LOTS: usize = 152;
+const OO_BASE_FREE_NATIVE: usize = 160;
+const OO_QUOTE_FREE_NATIVE: usize = 168;
+const OO_BIDS_QUOTE_LOTS: usize = 232;
+
+const LEVELS: u8 = 3;
+// 1_000 base per order, in lots of 0.001 base
+const ORDER_SIZE: u64 = 1_000_000_000;
+const BASE_LOT_SIZE: u64 = 1_000;
+const QUOTE_LOT_SIZE: u64 = 1;
+const PRICE: u64 = 1_000_000;
+
+fn openbook_pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &OPENBOOK_ID).0
+}
+
+struct Market {
+    market:             Pubkey,
+    market_authority:   Pubkey,
+    bids:               Pubkey,
+    asks:               Pubkey,
+    event_heap:         Pubkey,
+    market_base_vault:  Pubkey,
+    market_quote_vault: Pubkey,
+    open_orders:        Pubkey,
+}
+
+impl Market {
+    async fn new(env: &mut Env, tv: &TestVault) -> Self {
+        let payer = env.payer.pubkey();
+        let (market, bids, asks, event_heap) = (Keypair::new(), Keypair::new(), Keypair::new(), Keypair::new());
+        env.create_account(&bids, BOOK_SIDE_LEN, &OPENBOOK_ID).await;
+        env.create_account(&asks, BOOK_SIDE_LEN, &OPENBOOK_ID).await;
+        env.create_account(&event_heap, EVENT_HEAP_LEN, &OPENBOOK_ID).await;
+        let market_authority = openbook_pda(&[b"Market", market.pubkey().as_ref()]);
+        let market_base_vault = get_associated_token_address(&market_authority, &tv.base_mint);
+        let market_quote_vault = get_associated_token_address(&market_authority, &tv.quote_mint);
+
+        let mut data = sighash("create_market").to_vec();
+        "vault-test".to_string().serialize(&mut data).unwrap();
+        data.extend_from_slice(&0.1f32.to_le_bytes()); // oracle conf_filter
+        data.push(0); // oracle max_staleness_slots: None
+        data.extend_from_slice(&(QUOTE_LOT_SIZE as i64).to_le_bytes());
+        data.extend_from_slice(&(BASE_LOT_SIZE as i64).to_le_bytes());
+        data.extend_from_slice(&0i64.to_le_bytes()); // maker_fee
+        data.extend_from_slice(&0i64.to_le_bytes()); // taker_fee
+        data.extend_from_slice(&0i64.to_le_bytes()); // time_expiry
+        let none = AccountMeta::new_readonly(OPENBOOK_ID, false);
+        let create_market = Instruction {
+            program_id: OPENBOOK_ID,
+            accounts: vec![
+                AccountMeta::new(market.pubkey(), true),
+                AccountMeta::new_readonly(market_authority, false),
+                AccountMeta::new(bids.pubkey(), false),
+                AccountMeta::new(asks.pubkey(), false),
+                AccountMeta::new(event_heap.pubkey(), false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new(market_base_vault, false),
+                AccountMeta::new(market_quote_vault, false),
+                AccountMeta::new_readonly(tv.base_mint, false),
+                AccountMeta::new_readonly(tv.quote_mint, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+                AccountMeta::new_readonly(spl_token::ID, false),
+                AccountMeta::new_readonly(associated_token::ID, false),
+                none.clone(), // oracle_a
+                none.clone(), // oracle_b
+                AccountMeta::new_readonly(payer, false), // collect_fee_admin
+                none.clone(), // open_orders_admin
+                none.clone(), // consume_events_admin
+                none.clone(), // close_market_admin
+                AccountMeta::new_readonly(openbook_pda(&[b"__event_authority"]), false),
+                none,
+            ],
+            data,
+        };
+        env.send(&[create_market], &[&market]).await.unwrap();
+
+        Self {
+            market: market.pubkey(),
+            market_authority,
+            bids: bids.pubkey(),
+            asks: asks.pubkey(),
+            event_heap: event_heap.pubkey(),
+            market_base_vault,
+            market_quote_vault,
+            open_orders: openbook_pda(&[b"OpenOrders", tv.vault.as_ref(), &1u32.to_le_bytes()]),
+        }
+    }
+
+    /// Creates the vault's open orders account through `init_open_orders`.
+    async fn init_open_orders(&self, env: &mut Env, tv: &TestVault) {
+        let mut metas = accounts::InitOpenOrders {
+            vault: tv.vault,
+            authority: env.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        metas.extend([
+            AccountMeta::new_readonly(OPENBOOK_ID, false),
+            AccountMeta::new(openbook_pda(&[b"OpenOrdersIndexer", tv.vault.as_ref()]), false),
+            AccountMeta::new(self.open_orders, false),
+            AccountMeta::new_readonly(self.market, false),
+            AccountMeta::new_readonly(solana_sdk::sysvar::rent::ID, false),
+        ]);
+        let data = instruction::InitOpenOrders { name: "vault".to_string() }.data();
+        env.send(&[Instruction { program_id: dynamic_lp_mm::ID, accounts: metas, data }], &[])
+            .await
+            .unwrap();
+    }
+
+    fn nav_accounts(&self) -> Vec<AccountMeta> {
+        vec![AccountMeta::new_readonly(self.open_orders, false)]
+    }
+
+    fn venue_accounts(&self, env: &Env) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(OPENBOOK_ID, false),
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(self.open_orders, false),
+            AccountMeta::new(self.bids, false),
+            AccountMeta::new(self.asks, false),
+            AccountMeta::new(self.event_heap, false),
+            AccountMeta::new_readonly(self.market_authority, false),
+            AccountMeta::new(self.market_base_vault, false),
+            AccountMeta::new(self.market_quote_vault, false),
+            AccountMeta::new(env.payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ]
+    }
+
+    /// Locked ask base lots, locked bid quote lots, and free base and quote.
+    async fn position(&self, env: &mut Env) -> (u64, u64, u64, u64) {
+        let data = env.account(self.open_orders).await.unwrap().data;
+        let at = |o: usize| u64::from_le_bytes(data[o..o + 8].try_into().unwrap());
+        (at(OO_ASKS_BASE_LOTS), at(OO_BIDS_QUOTE_LOTS), at(OO_BASE_FREE_NATIVE), at(OO_QUOTE_FREE_NATIVE))
+    }
+}
+
+#[tokio::test]
+async fn openbook_order_round_trip() {
+    let Some(mut env) = start(&[("openbook_v2", OPENBOOK_ID)]).await else {
+        return;
+    };
+    let tv = TestVault::new(&mut env, 100).await;
+    let market = Market::new(&mut env, &tv).await;
+    tv.admin(
+        &mut env,
+        instruction::SetVenue {
+            venue:         VenueKind::OpenBookV2,
+            venue_program: OPENBOOK_ID,
+            venue_market:  market.market,
+        }
+        .data(),
+    )
+    .await;
+    tv.admin(
+        &mut env,
+        instruction::SetQuoteConfig {
+            spread_bps:     20,
+            level_step_bps: 20,
+            levels:         LEVELS,
+            order_size:     ORDER_SIZE,
+            base_lot_size:  BASE_LOT_SIZE,
+            quote_lot_size: QUOTE_LOT_SIZE,
+        }
+        .data(),
+    )
+    .await;
+    market.init_open_orders(&mut env, &tv).await;
+    tv.deposit(&mut env, 10_000_000_000, 10_000_000_000, &market.nav_accounts()).await;
+
+    // place: an ask and a bid per level
+    let mut remaining = market.nav_accounts();
+    remaining.extend(market.venue_accounts(&env));
+    tv.rebalance(&mut env, PRICE, &remaining, &[]).await;
+    let placed = tv.state(&mut env).await.clob.open_order_ids;
+    assert_eq!(placed.len(), 2 * LEVELS as usize);
+    let order_lots = ORDER_SIZE / BASE_LOT_SIZE;
+    let (asks, bids, _, _) = market.position(&mut env).await;
+    assert_eq!(asks, LEVELS as u64 * order_lots);
+    assert!(bids > 0);
+
+    // cancel: half the shares cancel the outer levels, the inner level keeps resting
+    let shares = env.token_balance(tv.user_shares).await;
+    let (base_before, quote_before) = (env.token_balance(tv.user_base).await, env.token_balance(tv.user_quote).await);
+    tv.withdraw(&mut env, shares / 2, &remaining).await;
+    let resting = tv.state(&mut env).await.clob.open_order_ids;
+    assert_eq!(resting, placed[..2].to_vec());
+    let (asks_left, bids_left, base_free, quote_free) = market.position(&mut env).await;
+    assert_eq!(asks_left, order_lots);
+    assert!(bids_left > 0 && bids_left * 2 <= bids);
+    assert_eq!((base_free, quote_free), (0, 0));
+    assert!(env.token_balance(tv.user_base).await > base_before);
+    assert!(env.token_balance(tv.user_quote).await > quote_before);
+
+    // settle: nothing is left free on the open orders account
+    let mut metas = accounts::SettleFunds {
+        vault: tv.vault,
+        base_vault: tv.base_vault,
+        quote_vault: tv.quote_vault,
+        token_program: spl_token::ID,
+    }
+    .to_account_metas(None);
+    metas.extend(market.venue_accounts(&env));
+    let settle = Instruction { program_id: dynamic_lp_mm::ID, accounts: metas, data: instruction::SettleFunds {}.data() };
+    env.send(&[settle], &[]).await.unwrap();
+    let (asks, bids, base_free, quote_free) = market.position(&mut env).await;
+    assert_eq!((asks, bids, base_free, quote_free), (asks_left, bids_left, 0, 0));
+}
@@ -0,0 +1,225 @@
+//! Whirlpool round trip: a rebalance opens a position and adds liquidity,
+//! a withdrawal takes the holder's share back out and collects fees.
+
+mod common;
+
+use anchor_lang::prelude::*;
+use anchor_lang::{system_program, InstructionData};
+use anchor_spl::associated_token;
+use anchor_spl::token::spl_token;
+use common::{sighash, start, Env, TestVault};
+use dynamic_lp_mm::venue::VenueKind;
+use dynamic_lp_mm::{instruction, math};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+const WHIRLPOOL_ID: Pubkey = pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+const TICK_SPACING: u16 = 64;
+const TICK_ARRAY_SIZE: i32 = 88;
+// one quote per base
+const PRICE: u64 = 1_000_000;
+
+fn whirlpool_pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &WHIRLPOOL_ID).0
+}
+
+fn tick_array_start(tick: i32) -> i32 {
+    let span = TICK_SPACING as i32 * TICK_ARRAY_SIZE;
+    tick.div_euclid(span) * span
+}
+
+struct Pool {
+    whirlpool:    Pubkey,
+    token_vault_a: Pubkey,
+    token_vault_b: Pubkey,
+}
+
+impl Pool {
+    /// Creates a whirlpool for the vault's mints at `PRICE`, with tick
+    /// arrays around it.
+    async fn new(env: &mut Env, tv: &TestVault) -> Self {
+        let payer = env.payer.pubkey();
+        let config = Keypair::new();
+        let mut data = sighash("initialize_config").to_vec();
+        for authority in [payer; 3] {
+            data.extend_from_slice(authority.as_ref());
+        }
+        data.extend_from_slice(&300u16.to_le_bytes()); // default_protocol_fee_rate
+        let init_config = Instruction {
+            program_id: WHIRLPOOL_ID,
+            accounts: vec![
+                AccountMeta::new(config.pubkey(), true),
+                AccountMeta::new(payer, true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data,
+        };
+        env.send(&[init_config], &[&config]).await.unwrap();
+
+        let fee_tier = whirlpool_pda(&[b"fee_tier", config.pubkey().as_ref(), &TICK_SPACING.to_le_bytes()]);
+        let mut data = sighash("initialize_fee_tier").to_vec();
+        data.extend_from_slice(&TICK_SPACING.to_le_bytes());
+        data.extend_from_slice(&3_000u16.to_le_bytes()); // default_fee_rate, 0.3%
+        let init_fee_tier = Instruction {
+            program_id: WHIRLPOOL_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(config.pubkey(), false),
+                AccountMeta::new(fee_tier, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new_readonly(payer, true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data,
+        };
+        env.send(&[init_fee_tier], &[]).await.unwrap();
+
+        let (whirlpool, bump) = Pubkey::find_program_address(
+            &[
+                b"whirlpool",
+                config.pubkey().as_ref(),
+                tv.base_mint.as_ref(),
+                tv.quote_mint.as_ref(),
+                &TICK_SPACING.to_le_bytes(),
+            ],
+            &WHIRLPOOL_ID,
+        );
+        let (vault_a, vault_b) = (Keypair::new(), Keypair::new());
+        let sqrt_price = math::sqrt_price_x64(math::price_to_x64(PRICE, 6, 6).unwrap());
+        let mut data = sighash("initialize_pool").to_vec();
+        data.push(bump);
+        data.extend_from_slice(&TICK_SPACING.to_le_bytes());
+        data.extend_from_slice(&sqrt_price.to_le_bytes());
+        let init_pool = Instruction {
+            program_id: WHIRLPOOL_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(config.pubkey(), false),
+                AccountMeta::new_readonly(tv.base_mint, false),
+                AccountMeta::new_readonly(tv.quote_mint, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new(whirlpool, false),
+                AccountMeta::new(vault_a.pubkey(), true),
+                AccountMeta::new(vault_b.pubkey(), true),
+                AccountMeta::new_readonly(fee_tier, false),
+                AccountMeta::new_readonly(spl_token::ID, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+                AccountMeta::new_readonly(solana_sdk::sysvar::rent::ID, false),
+            ],
+            data,
+        };
+        env.send(&[init_pool], &[&vault_a, &vault_b]).await.unwrap();
+
+        let pool = Self { whirlpool, token_vault_a: vault_a.pubkey(), token_vault_b: vault_b.pubkey() };
+        let span = TICK_SPACING as i32 * TICK_ARRAY_SIZE;
+        for start in [-span, 0] {
+            let mut data = sighash("initialize_tick_array").to_vec();
+            data.extend_from_slice(&start.to_le_bytes());
+            let init_tick_array = Instruction {
+                program_id: WHIRLPOOL_ID,
+                accounts: vec![
+                    AccountMeta::new_readonly(whirlpool, false),
+                    AccountMeta::new(payer, true),
+                    AccountMeta::new(pool.tick_array(start), false),
+                    AccountMeta::new_readonly(system_program::ID, false),
+                ],
+                data,
+            };
+            env.send(&[init_tick_array], &[]).await.unwrap();
+        }
+        pool
+    }
+
+    fn tick_array(&self, start: i32) -> Pubkey {
+        whirlpool_pda(&[b"tick_array", self.whirlpool.as_ref(), start.to_string().as_bytes()])
+    }
+
+    /// The whirlpool program, the whirlpool and its token vaults.
+    fn venue_accounts(&self) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(WHIRLPOOL_ID, false),
+            AccountMeta::new(self.whirlpool, false),
+            AccountMeta::new(self.token_vault_a, false),
+            AccountMeta::new(self.token_vault_b, false),
+        ]
+    }
+
+    /// Tick arrays holding the position's bounds.
+    fn tick_arrays(&self, tick_lower: i32, tick_upper: i32) -> [AccountMeta; 2] {
+        [
+            AccountMeta::new(self.tick_array(tick_array_start(tick_lower)), false),
+            AccountMeta::new(self.tick_array(tick_array_start(tick_upper)), false),
+        ]
+    }
+}
+
+#[tokio::test]
+async fn whirlpool_position_round_trip() {
+    let Some(mut env) = start(&[("whirlpool", WHIRLPOOL_ID)]).await else {
+        return;
+    };
+    let tv = TestVault::new(&mut env, 100).await;
+    let pool = Pool::new(&mut env, &tv).await;
+    tv.admin(
+        &mut env,
+        instruction::SetVenue {
+            venue:         VenueKind::Whirlpool,
+            venue_program: WHIRLPOOL_ID,
+            venue_market:  pool.whirlpool,
+        }
+        .data(),
+    )
+    .await;
+    tv.deposit(&mut env, 10_000_000_000, 10_000_000_000, &[]).await;
+
+    // open and fund the position of the price's band
+    let band = math::band_of_price(PRICE, 100).unwrap();
+    let (lo, hi) = math::band_price_bounds(band, 100).unwrap();
+    let (tick_lower, tick_upper) = math::align_tick_range(
+        math::tick_at_price(lo, 6, 6).unwrap(),
+        math::tick_at_price(hi, 6, 6).unwrap(),
+        TICK_SPACING,
+    );
+    let position_mint = Keypair::new();
+    let position = whirlpool_pda(&[b"position", position_mint.pubkey().as_ref()]);
+    let position_token_account = tv.position_token_account(&position_mint.pubkey());
+    let mut remaining = pool.venue_accounts();
+    remaining.extend([
+        AccountMeta::new(env.payer.pubkey(), true),
+        AccountMeta::new(position, false),
+        AccountMeta::new(position_mint.pubkey(), true),
+        AccountMeta::new(position_token_account, false),
+    ]);
+    remaining.extend(pool.tick_arrays(tick_lower, tick_upper));
+    remaining.extend([
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(solana_sdk::sysvar::rent::ID, false),
+        AccountMeta::new_readonly(associated_token::ID, false),
+    ]);
+    tv.rebalance(&mut env, PRICE, &remaining, &[&position_mint]).await;
+
+    let state = tv.state(&mut env).await;
+    assert_eq!(state.positions.len(), 1);
+    let open = state.positions[0];
+    assert_eq!((open.position, open.tick_lower, open.tick_upper), (position, tick_lower, tick_upper));
+    assert!(open.liquidity > 0);
+    assert_eq!(env.token_balance(position_token_account).await, 1);
+    let pool_a = env.token_balance(pool.token_vault_a).await;
+    let pool_b = env.token_balance(pool.token_vault_b).await;
+    assert!(pool_a + pool_b > 0);
+
+    // half the shares take half the liquidity back out, collecting fees on the way
+    let shares = env.token_balance(tv.user_shares).await;
+    let (base_before, quote_before) = (env.token_balance(tv.user_base).await, env.token_balance(tv.user_quote).await);
+    let mut remaining = vec![AccountMeta::new_readonly(pool.whirlpool, false), AccountMeta::new_readonly(position, false)];
+    remaining.extend(pool.venue_accounts());
+    remaining.extend([AccountMeta::new(position, false), AccountMeta::new_readonly(position_token_account, false)]);
+    remaining.extend(pool.tick_arrays(tick_lower, tick_upper));
+    tv.withdraw(&mut env, shares / 2, &remaining).await;
+
+    let state = tv.state(&mut env).await;
+    let left = state.positions[0].liquidity;
+    assert!(left < open.liquidity && left >= open.liquidity / 2, "{left} of {}", open.liquidity);
+    assert!(env.token_balance(pool.token_vault_a).await + env.token_balance(pool.token_vault_b).await < pool_a + pool_b);
+    assert!(env.token_balance(tv.user_base).await > base_before || env.token_balance(tv.user_quote).await > quote_before);
+    assert_eq!(env.token_balance(tv.user_shares).await, shares - shares / 2);
+}